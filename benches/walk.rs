@@ -0,0 +1,72 @@
+//! Times the sequential walk against the parallel one over a generated tree.
+//!
+//! `cargo bench --bench walk [-- <dir>]` walks `<dir>` instead if given.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use taginode::walk::{walk, walk_parallel, WalkOptions};
+
+const RUNS: u32 = 5;
+
+// A tree of width^depth directories with files files each.
+fn make_tree(dir: &Path, width: usize, depth: usize, files: usize) {
+    for i in 0..files {
+        fs::write(dir.join(format!("f{i}")), "").unwrap();
+    }
+    if depth == 0 {
+        return
+    }
+    for i in 0..width {
+        let sub = dir.join(format!("d{i}"));
+        fs::create_dir(&sub).unwrap();
+        make_tree(&sub, width, depth - 1, files);
+    }
+}
+
+// Best time of RUNS, and what the last run counted.
+fn time(mut run: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut count = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        count = run();
+        best = best.min(start.elapsed());
+    }
+    (best, count)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let generated = env::temp_dir().join(format!("taginode_bench_walk_{}", std::process::id()));
+    let root = match args.first() {
+        Some(root) => root.clone(),
+        None => {
+            fs::create_dir(&generated).unwrap();
+            make_tree(&generated, 6, 4, 10);
+            generated.to_str().unwrap().to_string()
+        },
+    };
+    let options = WalkOptions::default();
+
+    let (best, count) = time(|| {
+        let mut count = 0;
+        walk(&root, &options, &mut |_, _| { count += 1; true }, &mut |_, _| ());
+        count
+    });
+    println!("walk               {best:>12?}  {count} entries");
+    for threads in [1, 2, 4, 8] {
+        let (best, count) = time(|| {
+            let count = std::sync::atomic::AtomicUsize::new(0);
+            walk_parallel(&[&root], &options, threads,
+                &|_, _| { count.fetch_add(1, std::sync::atomic::Ordering::Relaxed); true }, &|_, _| ());
+            count.into_inner()
+        });
+        println!("walk_parallel -j {threads} {best:>12?}  {count} entries");
+    }
+
+    if args.is_empty() {
+        fs::remove_dir_all(&generated).unwrap();
+    }
+}
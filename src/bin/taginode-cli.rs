@@ -1,54 +1,116 @@
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
-use std::fs::DirEntry;
-use std::io::{Error, ErrorKind};
-use std::os::unix::prelude::MetadataExt;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
-use sqlite3::Connection;
-use taginode::INode;
-use taginode::opt::OptArg;
-use taginode::opt::OptCheck;
+use std::io;
+use std::io::Read;
+use std::sync::Mutex;
+use std::thread;
+use taginode::{Error, INode, InodeTags, TagStore};
+use taginode::completion::Shell;
+use taginode::config::{parse_bool, Config};
+use taginode::opt::{Complete, Opt, OptArg, OptCheck, OptList};
+use taginode::output::{Field, Format, Writer};
+use taginode::find::{Event, Find, Target};
+use taginode::walk::WalkOptions;
 
-fn usage(opt_check: &OptCheck) -> impl Fn() {
-    let usage_opt = taginode::opt::usage(opt_check);
-    move || {
-        eprintln!("Usage: taginode-cli [option] tag <file> <tag> \"tag1[,tag2,tag3...]\"");
-        eprintln!("Usage: taginode-cli [option] search [-d directory] \"tag1[,tag2,tag3...]\"");
-        eprintln!("Usage: taginode-cli [option] list tags");
-        eprintln!("Usage: taginode-cli [option] cat <file> [file]...");
-        eprintln!("{usage_opt}");
-        std::process::exit(1);
+// Subcommands, what their operands complete to, and their synopsis.
+const COMMANDS: [(&str, Complete, &str); 9] = [
+    ("tag", Complete::Files, "tag <file>... \"tag1[,tag2,tag3...]\""),
+    ("untag", Complete::Files, "untag <file>... \"tag1[,tag2,tag3...]\""),
+    ("tag-rename", Complete::Tags, "tag-rename <old> <new>"),
+    ("tag-merge", Complete::Tags, "tag-merge <src>... <dst>"),
+    ("search", Complete::TagList, "search [-d directory]... \"tag1[,tag2,tag3...]\" | \"<expr>\" [directory]...\n       \
+        expr: tag, \"quoted tag\", (expr), not expr, expr and expr, expr or expr"),
+    ("list", Complete::Words(&["tags"]), "list [-c] tags"),
+    ("cat", Complete::Files, "cat <file> [file]..."),
+    ("gc", Complete::Dirs, "gc [mount point | directory]...\n       \
+        default: mount points of tagged inodes; below other directories, only inodes with paths recorded there"),
+    ("completions", Complete::Words(&["bash", "zsh", "fish"]), "completions <bash|zsh|fish>"),
+];
+
+// The usage of command, or of every command if it is not one.
+fn usage(opt_check: &OptCheck, command: Option<&str>) -> String {
+    let command = command.filter(|command| COMMANDS.iter().any(|(name, _, _)| name == command));
+    let mut ret = Vec::new();
+    for (name, _, synopsis) in COMMANDS {
+        if command.is_none_or(|command| command == name) {
+            ret.push(format!("Usage: taginode-cli [option] {synopsis}"));
+        }
     }
+    ret.push(taginode::opt::usage(opt_check, command));
+    if let Some(path) = Config::path() {
+        ret.push(format!("Defaults are read from {} and TAGINODE_* variables.", path.display()));
+    }
+    ret.join("\n")
 }
 
 fn main() -> Result<(), Error>{
-    let mut default_db = env::var("HOME").unwrap();
-    default_db.push_str("/.taginode.db");
+    let args: Vec<String> = env::args().collect();
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("{}: {err}", args[0]);
+        std::process::exit(1);
+    });
 
     let opt_check = BTreeMap::from([
-        (b'f', (OptArg::Mandatory(default_db.as_str()), "-f <db>        specify db path to store data, default ~/.taginode.db"                           )),
-        (b'd', (                OptArg::Mandatory("."), "-d <directory> [search]specify path to search file by tags, default \".\""                      )),
-        (b'a', (                          OptArg::None, "-a             [search]ensable cross devices, default only search dev of path specified by -d"  )),
-        (b'u', (                          OptArg::None, "-u             [search]output same inode(default remove duplicate item"                         )),
-        // (b'v', (                          OptArg::None, "-v             verbose"                                                                    )),
-        // (b'l', (                          OptArg::None, "-l             follow symbolic links instead of symbolic file itself"                      )),
-        // (b'5', (                          OptArg::None, "-5             md5 mode instead of inode"                                                  )),
-        (b'V', (                          OptArg::None, "-V             version"                                                                         )),
+        (b'f', Opt { long: "db", arg: OptArg::Mandatory(config.db.as_deref().unwrap_or("")), value: "<db>",
+            complete: Complete::Files, commands: &[],
+            help: "specify db path to store data, default TAGINODE_DB or ~/.taginode.db" }),
+        (b'c', Opt { long: "count", commands: &["list"],
+            help: "show how many files use each tag, most used first", ..Opt::default() }),
+        (b'd', Opt { long: "directory", arg: OptArg::Mandatory(""), value: "<directory>", complete: Complete::Dirs,
+            commands: &["search"],
+            help: "specify path to search file by tags, repeatable, default TAGINODE_SEARCH_ROOTS or \".\"" }),
+        (b'a', Opt { long: "all-devices", arg: OptArg::Optional("yes"), value: "<yes|no>", commands: &["search"],
+            help: "ensable cross devices, default TAGINODE_CROSS_DEVICE or only search dev of path specified by -d",
+            ..Opt::default() }),
+        (b'u', Opt { long: "unique-paths", commands: &["search"],
+            help: "output same inode(default remove duplicate item", ..Opt::default() }),
+        (b'5', Opt { long: "content", commands: &["tag", "untag", "search", "cat"],
+            help: "match files by content hash instead of inode", ..Opt::default() }),
+        (b'i', Opt { long: "index", commands: &["search"],
+            help: "look up recorded paths first, only walk -d for files whose path went stale", ..Opt::default() }),
+        (b'n', Opt { long: "dry-run", commands: &["gc"],
+            help: "dry run, only report stale inodes", ..Opt::default() }),
+        (b'o', Opt { long: "output", arg: OptArg::Mandatory(&config.output), value: "<format>",
+            complete: Complete::Words(&["plain", "nul", "json", "jsonl", "csv"]), commands: &["search", "list", "cat", "gc"],
+            help: "output format: plain, nul, json, jsonl or csv, default TAGINODE_OUTPUT or plain" }),
+        (b'p', Opt { long: "prune", commands: &["untag"],
+            help: "delete tags which are no longer used by any file", ..Opt::default() }),
+        (b'0', Opt { long: "null", commands: &["tag", "untag"],
+            help: "paths read from stdin (\"-\") are separated by NUL instead of newline", ..Opt::default() }),
+        (b'x', Opt { long: "exclude", arg: OptArg::Mandatory(""), value: "<pattern>", commands: &["search", "gc"],
+            help: "leave out entries matching a gitignore-style pattern, repeatable, on top of TAGINODE_IGNORE",
+            ..Opt::default() }),
+        (b'm', Opt { long: "max-depth", arg: OptArg::Mandatory(""), value: "<depth>", commands: &["search", "gc"],
+            help: "walk at most this many directories below each directory given", ..Opt::default() }),
+        (b'j', Opt { long: "threads", arg: OptArg::Mandatory(""), value: "<n>", commands: &["search"],
+            help: "walk directories on n threads, default one per CPU", ..Opt::default() }),
+        (b'h', Opt { long: "help", help: "show this help, or that of the subcommand given", ..Opt::default() }),
+        (b'V', Opt { long: "version", help: "version", ..Opt::default() }),
     ]);
-    let usage = usage(&opt_check);
 
-    let args: Vec<String> = env::args().collect();
-    let (options, operands) = 
-        taginode::opt::get_opt_per(&args[1..], &opt_check).unwrap_or_else(|err| {
+    let (option_lists, operands) =
+        taginode::opt::get_opt_command(&args[1..], &opt_check).unwrap_or_else(|err| {
             eprintln!("{}: {}",args[0], err);
-            usage();
+            eprintln!("{}", usage(&opt_check, None));
             std::process::exit(1);
         });
+    let options: HashMap<u8, &str> = option_lists.iter()
+        .filter_map(|(k, v)| Some((*k, *v.last()?)))
+        .collect();
+    let command = operands.first().copied();
+    if options.contains_key(&b'h') {
+        println!("{}", usage(&opt_check, command));
+        return Ok(())
+    }
+    let usage = || {
+        eprintln!("{}", usage(&opt_check, command));
+        std::process::exit(1);
+    };
 
-    if options.get(&b'V').is_some() {
+    if options.contains_key(&b'V') {
         println!("{}", env!("CARGO_PKG_VERSION"));
         return Ok(())
     }
@@ -56,222 +118,376 @@ fn main() -> Result<(), Error>{
         usage();
     }
 
-    let db_path = options.get(&b'f').copied().unwrap_or(default_db.as_str());
-    let db = taginode::sql::init(&db_path);
-
     let ret = match operands[0] {
-        "tag" => tag(&operands[1..], db),
-        "search" => search(&operands[1..], options, db),
-        "list" => list(&operands[1..], db),
-        "cat" => cat(&operands[1..], db),
-        _ => Err(Error::new(ErrorKind::Other, "")),
+        // needs no database
+        "completions" => completions(&operands[1..], &opt_check),
+        command => {
+            let Some(db_path) = options.get(&b'f').copied() else {
+                eprintln!("{}: no database, set HOME or TAGINODE_DB, or use -f", args[0]);
+                std::process::exit(1);
+            };
+            let db = TagStore::open(db_path).unwrap_or_else(|err| {
+                eprintln!("{}: {db_path}: {err}", args[0]);
+                std::process::exit(1);
+            });
+            match command {
+                "tag" => tag(&operands[1..], options, db),
+                "untag" => untag(&operands[1..], options, db),
+                "tag-rename" => tag_rename(&operands[1..], db),
+                "tag-merge" => tag_merge(&operands[1..], db),
+                "search" => search(&operands[1..], options, &option_lists, &config, db),
+                "list" => list(&operands[1..], options, db),
+                "cat" => cat(&operands[1..], options, db),
+                "gc" => gc(&operands[1..], options, &option_lists, &config, db),
+                _ => err_str(""),
+            }
+        },
     };
-    if ret.is_err() {
-        eprintln!("{}", ret.as_ref().unwrap_err());
+    if let Err(err) = &ret {
+        eprintln!("{err}");
         usage();
     }
     ret
 }
 
-fn tag(operands: &[&str], db: Connection) -> Result<(), Error> {
+fn tag(operands: &[&str], options: HashMap<u8, &str>, db: TagStore) -> Result<(), Error> {
     if operands.len() < 2 {
         return err_str("");
     }
-    let files = &operands[0..1];
-    let tag_names: Vec<&str> = operands[1].split(",").collect();
-    eprintln!("tag_names: {:?}, files: {:?}", tag_names, files);
+    let files = read_files(&operands[..operands.len()-1], options.contains_key(&b'0'))?;
+    let tag_names = taginode::query::split_tags(operands[operands.len()-1]);
+    eprintln!("tag_names: {:?}, files: {}", tag_names, files.len());
 
-    for file in files {
-        let metadata = fs::symlink_metadata(file.to_string());
-        let metadata = match metadata {
-            Ok(metadata) => metadata,
-            Err(error) => {
-                eprintln!("{:?}", error);
-                continue;
-            },
-        };
-        let btime = metadata.created();
-        let btime = match btime {
-            Ok(btime) => { 
-                match btime.duration_since(UNIX_EPOCH) {
-                    Ok(btime) => Some(btime.as_secs()),
-                    Err(error) => {
-                        eprintln!("Warning: {:?}", error);
-                        None
-                    }
-                }
-            },
-            Err(error) => {
-                eprintln!("Warning: {:?}", error);
-                None
-            }
-        };
-        taginode::add(&db, 
-            &vec![ INode{ device: metadata.dev(), number: metadata.ino(), btime } ],
-            &tag_names,
-        );
+    if options.contains_key(&b'5') {
+        let files = file_contents(&files);
+        return db.add_content(&files, &tag_names);
     }
-    Ok(())
+    let entries = file_inodes(&files);
+    let inodes: Vec<INode> = entries.iter().map(|(inode, _)| inode.clone()).collect();
+    db.transaction(|db| {
+        db.add(&inodes, &tag_names)?;
+        db.record_paths(&entries)
+    })
 }
 
-fn search(operands: &[&str], options: HashMap<u8, &str>, db: Connection) -> Result<(), Error> {
-    if operands.len() != 1 {
+fn untag(operands: &[&str], options: HashMap<u8, &str>, db: TagStore) -> Result<(), Error> {
+    if operands.len() < 2 {
         return err_str("");
     }
-    let tag_names: Vec<&str> = operands[0].split(",").collect();
-    let paths = vec![options.get(&b'd').copied().unwrap_or("")];
-    eprintln!("tag_names: {:?}, paths: {:?}", tag_names, paths);
+    let files = read_files(&operands[..operands.len()-1], options.contains_key(&b'0'))?;
+    let tag_names = taginode::query::split_tags(operands[operands.len()-1]);
+    eprintln!("tag_names: {:?}, files: {}", tag_names, files.len());
 
-    let inodes = taginode::get_inodes(&db, &tag_names);
-    let mut dev_inode_map: HashMap<u64, HashMap<u64, &INode>> = HashMap::new();
-    for inode in &inodes {
-        let inode_map = dev_inode_map.get_mut(&inode.device);
-        match inode_map {
-            Some(inode_map) => {
-                inode_map.insert(inode.number, inode);
-            }, 
-            None => {
-                let mut inode_map = HashMap::new();
-                inode_map.insert(inode.number, inode);
-                dev_inode_map.insert(inode.device, inode_map);
+    if options.contains_key(&b'5') {
+        let files = file_contents(&files);
+        return db.remove_content(&files, &tag_names, options.contains_key(&b'p'));
+    }
+    let inodes: Vec<INode> = file_inodes(&files).into_iter().map(|(inode, _)| inode).collect();
+    db.remove(&inodes, &tag_names, options.contains_key(&b'p'))
+}
+
+fn tag_rename(operands: &[&str], db: TagStore) -> Result<(), Error> {
+    if operands.len() != 2 {
+        return err_str("");
+    }
+    db.rename_tag(operands[0], operands[1])
+}
+
+fn tag_merge(operands: &[&str], db: TagStore) -> Result<(), Error> {
+    if operands.len() < 2 {
+        return err_str("");
+    }
+    let (sources, dst) = (&operands[..operands.len()-1], operands[operands.len()-1]);
+    db.merge_tags(sources, dst)
+}
+
+// Expands the file operands of tag/untag, where "-" stands for a list of paths
+// read from stdin, one per line or NUL-separated with -0.
+fn read_files(operands: &[&str], nul: bool) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for operand in operands {
+        if *operand != "-" {
+            files.push(operand.to_string());
+            continue;
+        }
+        let mut input = Vec::new();
+        io::stdin().read_to_end(&mut input)?;
+        let sep = if nul { b'\0' } else { b'\n' };
+        for path in input.split(|b| *b == sep) {
+            if !path.is_empty() {
+                files.push(String::from_utf8_lossy(path).into_owned());
             }
-        };
+        }
     }
+    Ok(files)
+}
 
-    let stack = 
-    let mut occur: Option<HashMap<u64, HashMap<u64, String>>> = match options.get(&b'u') {
-        Some(_) => None,
-        None => Some(HashMap::new()),
-    };
-    for path in paths {
-        match process_file(&dev_inode_map, path, options.get(&b'a').is_some(), &mut occur) {
-            Err(error) => eprintln!("{path}: {error:?}"),
-            _ => (),
+fn file_inodes(files: &[String]) -> Vec<(INode, String)> {
+    let mut inodes = Vec::new();
+    for file in files {
+        match fs::symlink_metadata(file) {
+            Ok(metadata) => inodes.push((INode::from_metadata(&metadata), file.clone())),
+            Err(error) => eprintln!("{file}: {error}"),
         }
     }
-    Ok(())
+    inodes
 }
 
-fn process_file(dev_inode_map: &HashMap<u64, HashMap<u64, &INode>>, f: &str, cross_dev: bool, occur: &mut Option<HashMap<u64, HashMap<u64, String>>>) -> Result<(), Error> {
-    let metadata = fs::symlink_metadata(f)?;
-    if occur.is_some() {
-        let occur = occur.as_mut().unwrap();
-        match occur.get_mut(&metadata.dev()) {
-            Some(s) => {
-                match s.get_mut(&metadata.ino()) {
-                    Some(old) => {
-                        eprintln!("{}: same file as '{}'",f, old);
-                        return Ok(())
-                    }, 
-                    None => {
-                        s.insert(metadata.ino(), f.to_string());
-                    },
-                }
-            },
-            None => {
-                let mut inode_map = HashMap::new();
-                inode_map.insert(metadata.ino(), f.to_string());
-                occur.insert(metadata.dev(), inode_map);
-            },
+fn file_contents(files: &[String]) -> Vec<taginode::File> {
+    let mut contents = Vec::new();
+    for file in files {
+        match taginode::content::hash(file) {
+            Ok(content) => contents.push(content),
+            Err(error) => eprintln!("{file}: {error}"),
         }
     }
-    match dev_inode_map.get(&metadata.dev()) {
-        Some(inode_map) => {
-        match inode_map.get(&metadata.ino()) {
-            None => (),
-            Some(ino) => {
-                let created = get_file_btime(metadata.created());
-                if ino.btime == None || created.is_none() || ino.btime.unwrap() == created.unwrap() {
-                    println!("{} ", f);
-                }
+    contents
+}
+
+fn search(operands: &[&str], options: HashMap<u8, &str>, option_lists: &OptList, config: &Config, db: TagStore) -> Result<(), Error> {
+    if operands.is_empty() {
+        return err_str("");
+    }
+    let query = taginode::query::parse(operands[0])?;
+    let roots: Vec<&str> = config.search_roots.iter().map(String::as_str).collect();
+    let paths = if operands.len() > 1 { &operands[1..] } else { option_lists.get(&b'd').unwrap_or(&roots) };
+    let cross_dev = match options.get(&b'a') {
+        Some(value) => parse_bool(value).ok_or_else(|| io::Error::other(format!("invalid -a argument '{value}'")))?,
+        None => config.cross_device,
+    };
+    let threads = match options.get(&b'j') {
+        Some(value) => value.parse().map_err(|_| io::Error::other(format!("invalid -j argument '{value}'")))?,
+        None => thread::available_parallelism().map_or(1, usize::from),
+    };
+    let walk_options = walk_options(&options, option_lists, config, cross_dev)?;
+    let find = finder(walk_options, options.contains_key(&b'u'), threads);
+    eprintln!("query: {:?}, paths: {:?}", query, paths);
+
+    let mut out = writer(&options, &["path"])?;
+    if options.contains_key(&b'5') {
+        let hashes: HashSet<String> = db.search_content(&query)?
+            .into_iter().map(|file| file.sha256).collect();
+        let out = find_paths(|event| { find(Target::Contents(&hashes)).run(paths, event); Ok(()) }, out, |out, path, inode, file| {
+            let mut record = inode_record(path, &inode);
+            if let Some(file) = file {
+                record.push(("md5", file.md5.into()));
+                record.push(("sha256", file.sha256.into()));
             }
-        };
-        },
-        None if !cross_dev => return Ok(()), 
-        _ => (),
+            out.write(&record)
+        })?;
+        return Ok(out.finish()?)
     }
-    if metadata.is_dir() {
-        if metadata.is_symlink() {
-            return Ok(())
+    // Without -i the matches go straight from the cursor into the map.
+    let mut dev_inode_map: HashMap<u64, HashMap<u64, INode>> = HashMap::new();
+    let insert = |dev_inode_map: &mut HashMap<u64, HashMap<u64, INode>>, inode: INode| {
+        dev_inode_map.entry(inode.device).or_default().insert(inode.number, inode);
+    };
+    if options.contains_key(&b'i') {
+        let inodes = db.search(&query)?;
+        let (found, stale) = db.locate(&inodes)?;
+        let mut located_map = HashMap::new();
+        for (inode, _) in &found {
+            insert(&mut located_map, inode.clone());
         }
-        let paths = fs::read_dir(f)?;
-        for path in paths {
-            match path {
-                Ok(entry) => {
-                    let p = entry.path();
-                    let p= p.to_str().unwrap();
-                    match process_file(&dev_inode_map, p, cross_dev, occur) {
-                        Err(error) => eprintln!("{p}: {error:?}"),
-                        _ => (),
-                    }
-                }
-                Err(error) => eprintln!("{f} {error:?}"),
-            };
+        let located: Vec<&str> = found.iter().map(|(_, path)| path.as_str()).collect();
+        out = find_paths(|event| find(Target::Inodes(&located_map)).located(paths, &located, event), out, |out, path, inode, _| {
+            out.write(&inode_record(path, &inode))
+        })?;
+        if stale.is_empty() {
+            return Ok(out.finish()?);
+        }
+        for inode in stale {
+            insert(&mut dev_inode_map, inode);
+        }
+    } else {
+        for inode in db.search_iter(&query)? {
+            insert(&mut dev_inode_map, inode?);
         }
     }
-    Ok(())
+
+    let (out, hits) = find_paths(|event| { find(Target::Inodes(&dev_inode_map)).run(paths, event); Ok(()) }, (out, Vec::new()), |(out, hits), path, inode, _| {
+        out.write(&inode_record(path, &inode))?;
+        hits.push((inode, path.to_string()));
+        Ok(())
+    })?;
+    out.finish()?;
+    db.record_paths(&hits)
+}
+
+// Standard output unlocked, so that the threads of a search can share it.
+fn writer(options: &HashMap<u8, &str>, plain: &[&str]) -> Result<Writer<io::Stdout>, Error> {
+    let format: Format = options.get(&b'o').copied().unwrap_or("plain").parse()?;
+    Ok(Writer::new(io::stdout(), format, plain))
 }
 
-fn list(args: &[&str], db: Connection) -> Result<(), Error> {
-    if args.len() < 1 || args[0] != "tags" {
+fn inode_record(path: &str, inode: &INode) -> Vec<(&'static str, Field)> {
+    vec![
+        ("path", path.into()),
+        ("device", inode.device.into()),
+        ("inode", inode.number.into()),
+        ("btime", inode.btime.into()),
+    ]
+}
+
+// The Find of a target with the walk settings of a search.
+fn finder(options: WalkOptions, duplicates: bool, threads: usize) -> impl Fn(Target<'_>) -> Find<'_> {
+    move |target| Find { target, options: options.clone(), duplicates, threads }
+}
+
+// Runs a Find through run, passing what it finds to found along with state,
+// one at a time. The first error of found stops it.
+fn find_paths<T: Send>(
+    run: impl FnOnce(&(dyn Fn(Event) -> bool + Sync)) -> io::Result<()>,
+    state: T,
+    found: impl Fn(&mut T, &str, INode, Option<taginode::File>) -> io::Result<()> + Sync,
+) -> io::Result<T> {
+    let state = Mutex::new((state, None));
+    run(&|event| match event {
+        Event::Found(path, inode, file) => {
+            let mut state = state.lock().unwrap();
+            let (state, failed) = &mut *state;
+            match found(state, path, inode, file) {
+                Ok(()) => true,
+                Err(error) => {
+                    *failed = Some(error);
+                    false
+                },
+            }
+        },
+        Event::Duplicate(path, first) => {
+            eprintln!("{path}: same file as '{first}'");
+            true
+        },
+        Event::Error(path, error) => {
+            eprintln!("{path}: {error}");
+            true
+        },
+    })?;
+    match state.into_inner().unwrap() {
+        (_, Some(error)) => Err(error),
+        (state, None) => Ok(state),
+    }
+}
+
+// The walk rules of the config, with those of -x and -m on top.
+fn walk_options(options: &HashMap<u8, &str>, option_lists: &OptList, config: &Config, cross_dev: bool) -> io::Result<WalkOptions> {
+    let mut exclude = config.ignore.clone();
+    exclude.extend(option_lists.get(&b'x').into_iter().flatten().map(|pattern| pattern.to_string()));
+    let max_depth = match options.get(&b'm') {
+        Some(value) => Some(value.parse().map_err(|_| io::Error::other(format!("invalid -m argument '{value}'")))?),
+        None => None,
+    };
+    Ok(WalkOptions { cross_dev, exclude, ignore_files: config.ignore_files.clone(), max_depth })
+}
+
+fn list(args: &[&str], options: HashMap<u8, &str>, db: TagStore) -> Result<(), Error> {
+    if args.is_empty() || args[0] != "tags" {
         return err_str("");
     }
 
-    let tag_names = taginode::list_tags(&db);
-    for tag_name in tag_names {
-        println!("{tag_name:?}")
+    if options.contains_key(&b'c') {
+        let mut out = writer(&options, &["tag", "count"])?;
+        for tag in db.list_tag_counts_iter()? {
+            let tag = tag?;
+            out.write(&[("tag", tag.name.into()), ("count", tag.inode_num.into())])?;
+        }
+        return Ok(out.finish()?)
     }
-    Ok(())
+    let mut out = writer(&options, &["tag"])?;
+    for tag_name in db.list_tags_iter()? {
+        out.write(&[("tag", tag_name?.into())])?;
+    }
+    Ok(out.finish()?)
 }
 
-fn cat(args: &[&str], db: Connection) -> Result<(), Error> {
-    if args.len() < 1 {
+fn cat(args: &[&str], options: HashMap<u8, &str>, db: TagStore) -> Result<(), Error> {
+    if args.is_empty() {
         return err_str("");
     }
+    if options.contains_key(&b'5') {
+        let mut out = writer(&options, &["path", "tags"])?;
+        for path in args {
+            match taginode::content::hash(path) {
+                Ok(file) => {
+                    let tag_names = db.get_content_tags(&file)?;
+                    out.write(&[
+                        ("path", (*path).into()),
+                        ("md5", file.md5.into()),
+                        ("sha256", file.sha256.into()),
+                        ("tags", tag_names.into()),
+                    ])?;
+                },
+                Err(err) => eprintln!("{path}: {err}"),
+            }
+        }
+        return Ok(out.finish()?)
+    }
+    let mut out = writer(&options, &["path", "tags", "status"])?;
     for path in args {
         let metadata = fs::symlink_metadata(path);
-        print!("{}:    ", path);
         match metadata {
             Ok(metadata) => {
-                let tag_names = taginode::get_tags(
-                    &db, 
-                    INode { 
-                        device: metadata.dev(), 
-                        number: metadata.ino(), 
-                        btime: get_file_btime(metadata.created()),
-                    },
-                );
-                println!("{tag_names:?}")
+                let inode = INode::from_metadata(&metadata);
+                let mut record = inode_record(path, &inode);
+                let (status, tag_names) = match db.get_tags(&inode)? {
+                    InodeTags::Unknown => ("unknown", Vec::new()),
+                    // the tags belong to the file that had the inode before
+                    InodeTags::Stale { .. } => ("stale", Vec::new()),
+                    InodeTags::Tagged(tag_names) if tag_names.is_empty() => ("untagged", tag_names),
+                    InodeTags::Tagged(tag_names) => ("tagged", tag_names),
+                };
+                record.push(("tags", tag_names.into()));
+                record.push(("status", status.into()));
+                out.write(&record)?;
             },
-            Err(err) => eprintln!("{}", err),
+            Err(err) => eprintln!("{path}: {err}"),
         }
     }
-    Ok(())
+    Ok(out.finish()?)
 }
 
-fn get_file_btime(btime: std::io::Result<SystemTime>) -> Option<u64> {
-// match btime {
-//     Ok(btime) => { 
-//         match btime.duration_since(UNIX_EPOCH) {
-//             Ok(btime) => Some(btime.as_secs()),
-//             Err(error) => {
-//                 // eprintln!("Warning: {:?}", error);
-//                 None
-//             }
-//         }
-//     },
-//     Err(error) => {
-//         // eprintln!("Warning: {:?}", error);
-//         None
-//     }
-// }
-    if btime.is_ok() && btime.as_ref().unwrap().duration_since(UNIX_EPOCH).is_ok() {
-        Some(btime.unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs())
+fn gc(args: &[&str], options: HashMap<u8, &str>, option_lists: &OptList, config: &Config, db: TagStore) -> Result<(), Error> {
+    let mount_points;
+    let roots = if args.is_empty() {
+        mount_points = db.tagged_mount_points()?;
+        mount_points.iter().map(String::as_str).collect()
     } else {
-        None
+        args.to_vec()
+    };
+    let dry_run = options.contains_key(&b'n');
+    eprintln!("roots: {:?}, dry run: {}", roots, dry_run);
+    let mut out = writer(&options, &["status", "device", "inode"])?;
+
+    let report = db.gc(&roots, &walk_options(&options, option_lists, config, false)?, dry_run)?;
+    for (status, inodes) in [("missing", &report.missing), ("reused", &report.reused)] {
+        for inode in inodes {
+            out.write(&[
+                ("status", status.into()),
+                ("device", inode.device.into()),
+                ("inode", inode.number.into()),
+                ("btime", inode.btime.into()),
+            ])?;
+        }
     }
+    out.finish()?;
+    eprintln!("{} entries scanned, {} skipped, {} missing, {} reused, {} relations {}",
+        report.scanned, report.skipped, report.missing.len(), report.reused.len(), report.relations,
+        if dry_run { "would be dropped" } else { "dropped" });
+    Ok(())
+}
+
+// Prints the completion script of a shell, built from the option table and
+// COMMANDS.
+fn completions(args: &[&str], opt_check: &OptCheck) -> Result<(), Error> {
+    if args.len() != 1 {
+        return err_str("");
+    }
+    let shell: Shell = args[0].parse()?;
+    let commands: Vec<(&str, Complete)> = COMMANDS.iter().map(|(name, complete, _)| (*name, *complete)).collect();
+    print!("{}", taginode::completion::generate(shell, "taginode-cli", &commands, opt_check));
+    Ok(())
 }
 
 fn err_str(msg: &str) -> Result<(), Error> {
-    return Err(Error::new(ErrorKind::Other, msg));
+    Err(Error::Io(io::Error::other(msg)))
 }
\ No newline at end of file
@@ -0,0 +1,267 @@
+//! Shell completion scripts generated from the option table and the list of
+//! subcommands, so they cannot drift from what the binary accepts.
+
+use std::io;
+use std::str::FromStr;
+use crate::opt::{Complete, Opt, OptArg, OptCheck};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Shell> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(io::Error::other(format!("unknown shell '{s}'"))),
+        }
+    }
+}
+
+/// The completion script of `program` for `shell`. `commands` are the
+/// subcommands with what their operands complete to; tags are listed by
+/// running `program list tags -o plain`.
+pub fn generate(shell: Shell, program: &str, commands: &[(&str, Complete)], opt_check: &OptCheck) -> String {
+    match shell {
+        Shell::Bash => bash(program, commands, opt_check),
+        Shell::Zsh => zsh(program, commands, opt_check),
+        Shell::Fish => fish(program, commands, opt_check),
+    }
+}
+
+// The spellings of an option, e.g. ["-f", "--db"].
+fn names(k: u8, opt: &Opt) -> Vec<String> {
+    let mut names = vec![format!("-{}", k as char)];
+    if !opt.long.is_empty() {
+        names.push(format!("--{}", opt.long));
+    }
+    names
+}
+
+// Options followed by a separate argument, which is not a subcommand.
+fn arg_names(opt_check: &OptCheck) -> Vec<String> {
+    opt_check.iter()
+        .filter(|(_, opt)| matches!(opt.arg, OptArg::Mandatory(_)))
+        .flat_map(|(k, opt)| names(*k, opt))
+        .collect()
+}
+
+fn command_names(commands: &[(&str, Complete)]) -> String {
+    commands.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(" ")
+}
+
+// Every spelling of the options that apply to command, or to all commands.
+fn option_names(opt_check: &OptCheck, command: Option<&str>) -> String {
+    opt_check.iter()
+        .filter(|(_, opt)| opt.commands.is_empty() || command.is_some_and(|command| opt.commands.contains(&command)))
+        .flat_map(|(k, opt)| names(*k, opt))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn function_name(program: &str) -> String {
+    format!("_{}", program.replace(|c: char| !c.is_ascii_alphanumeric(), "_"))
+}
+
+fn bash_complete(complete: &Complete, function: &str) -> String {
+    match complete {
+        Complete::Nothing => String::from("COMPREPLY=()"),
+        Complete::Files => String::from("COMPREPLY=( $(compgen -f -- \"${cur}\") )"),
+        Complete::Dirs => String::from("COMPREPLY=( $(compgen -d -- \"${cur}\") )"),
+        Complete::Words(words) => format!("COMPREPLY=( $(compgen -W \"{}\" -- \"${{cur}}\") )", words.join(" ")),
+        Complete::Tags => format!("{function}_tags"),
+        Complete::TagList => format!("{function}_tags ,"),
+    }
+}
+
+fn bash(program: &str, commands: &[(&str, Complete)], opt_check: &OptCheck) -> String {
+    let function = function_name(program);
+    let mut s = String::new();
+    s.push_str(&format!("{function}_tags()\n{{\n"));
+    s.push_str("\tlocal tags\n");
+    s.push_str(&format!("\ttags=\"$({program} list tags -o plain 2>/dev/null)\"\n"));
+    s.push_str("\tif [[ \"$1\" == , && \"${cur}\" == *,* ]]; then\n");
+    s.push_str("\t\tCOMPREPLY=( $(compgen -W \"${tags}\" -P \"${cur%,*},\" -- \"${cur##*,}\") )\n");
+    s.push_str("\telse\n");
+    s.push_str("\t\tCOMPREPLY=( $(compgen -W \"${tags}\" -- \"${cur}\") )\n");
+    s.push_str("\tfi\n}\n\n");
+
+    s.push_str(&format!("{function}()\n{{\n"));
+    s.push_str("\tlocal cur prev command i\n");
+    s.push_str("\tCOMPREPLY=()\n");
+    s.push_str("\tcur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    s.push_str("\tprev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    s.push_str("\tfor ((i = 1; i < COMP_CWORD; i++)); do\n");
+    s.push_str("\t\tcase \"${COMP_WORDS[i]}\" in\n");
+    let arg_names = arg_names(opt_check);
+    if !arg_names.is_empty() {
+        s.push_str(&format!("\t\t\t{}) ((i++)) ;;\n", arg_names.join("|")));
+    }
+    s.push_str("\t\t\t-*) ;;\n");
+    s.push_str("\t\t\t*) command=\"${COMP_WORDS[i]}\"; break ;;\n");
+    s.push_str("\t\tesac\n\tdone\n\n");
+
+    s.push_str("\tcase \"${prev}\" in\n");
+    for (k, opt) in opt_check {
+        if matches!(opt.arg, OptArg::Mandatory(_)) {
+            s.push_str(&format!("\t\t{})\n\t\t\t{}\n\t\t\treturn 0\n\t\t\t;;\n",
+                names(*k, opt).join("|"), bash_complete(&opt.complete, &function)));
+        }
+    }
+    s.push_str("\tesac\n\n");
+
+    s.push_str("\tif [[ \"${cur}\" == -* ]]; then\n");
+    s.push_str("\t\tcase \"${command}\" in\n");
+    for (name, _) in commands {
+        s.push_str(&format!("\t\t\t{name}) COMPREPLY=( $(compgen -W \"{}\" -- \"${{cur}}\") ) ;;\n",
+            option_names(opt_check, Some(name))));
+    }
+    s.push_str(&format!("\t\t\t*) COMPREPLY=( $(compgen -W \"{}\" -- \"${{cur}}\") ) ;;\n",
+        option_names(opt_check, None)));
+    s.push_str("\t\tesac\n\t\treturn 0\n\tfi\n\n");
+
+    s.push_str("\tcase \"${command}\" in\n");
+    s.push_str(&format!("\t\t\"\") COMPREPLY=( $(compgen -W \"{}\" -- \"${{cur}}\") ) ;;\n", command_names(commands)));
+    for (name, complete) in commands {
+        s.push_str(&format!("\t\t{name}) {} ;;\n", bash_complete(complete, &function)));
+    }
+    s.push_str("\tesac\n}\n");
+    s.push_str(&format!("complete -o nospace -F {function} {program}\n"));
+    s
+}
+
+fn zsh_complete(complete: &Complete, function: &str) -> String {
+    match complete {
+        Complete::Nothing => String::from("return 1"),
+        Complete::Files => String::from("_files"),
+        Complete::Dirs => String::from("_files -/"),
+        Complete::Words(words) => format!("compadd -- {}", words.join(" ")),
+        Complete::Tags => format!("{function}_tags"),
+        Complete::TagList => format!("compset -P '*,'; {function}_tags"),
+    }
+}
+
+fn zsh(program: &str, commands: &[(&str, Complete)], opt_check: &OptCheck) -> String {
+    let function = function_name(program);
+    let mut s = format!("#compdef {program}\n\n");
+    s.push_str(&format!("{function}_tags() {{\n"));
+    s.push_str("\tlocal -a tags\n");
+    s.push_str(&format!("\ttags=(${{(f)\"$({program} list tags -o plain 2>/dev/null)\"}})\n"));
+    s.push_str("\tcompadd -a tags\n}\n\n");
+
+    s.push_str(&format!("{function}() {{\n"));
+    s.push_str("\tlocal command i\n");
+    s.push_str("\tfor ((i = 2; i < CURRENT; i++)); do\n");
+    s.push_str("\t\tcase ${words[i]} in\n");
+    let arg_names = arg_names(opt_check);
+    if !arg_names.is_empty() {
+        s.push_str(&format!("\t\t\t{}) ((i++)) ;;\n", arg_names.join("|")));
+    }
+    s.push_str("\t\t\t-*) ;;\n");
+    s.push_str("\t\t\t*) command=${words[i]}; break ;;\n");
+    s.push_str("\t\tesac\n\tdone\n\n");
+
+    s.push_str("\tcase ${words[CURRENT-1]} in\n");
+    for (k, opt) in opt_check {
+        if matches!(opt.arg, OptArg::Mandatory(_)) {
+            s.push_str(&format!("\t\t{}) {}; return ;;\n",
+                names(*k, opt).join("|"), zsh_complete(&opt.complete, &function)));
+        }
+    }
+    s.push_str("\tesac\n\n");
+
+    s.push_str("\tif [[ ${words[CURRENT]} == -* ]]; then\n");
+    s.push_str("\t\tcase $command in\n");
+    for (name, _) in commands {
+        s.push_str(&format!("\t\t\t{name}) compadd -- {} ;;\n", option_names(opt_check, Some(name))));
+    }
+    s.push_str(&format!("\t\t\t*) compadd -- {} ;;\n", option_names(opt_check, None)));
+    s.push_str("\t\tesac\n\t\treturn\n\tfi\n\n");
+
+    s.push_str("\tcase $command in\n");
+    s.push_str(&format!("\t\t'') compadd -- {} ;;\n", command_names(commands)));
+    for (name, complete) in commands {
+        s.push_str(&format!("\t\t{name}) {} ;;\n", zsh_complete(complete, &function)));
+    }
+    s.push_str("\tesac\n}\n\n");
+    s.push_str(&format!("if [ \"$funcstack[1]\" = \"{function}\" ]; then\n"));
+    s.push_str(&format!("\t{function} \"$@\"\nelse\n\tcompdef {function} {program}\nfi\n"));
+    s
+}
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// A condition that holds when the subcommand is one of commands.
+fn fish_command_is(function: &str, commands: &[&str]) -> String {
+    format!("{function}_command | string match -q -r -- '^({})$'", commands.join("|"))
+}
+
+fn fish_complete(complete: &Complete, function: &str) -> String {
+    match complete {
+        Complete::Nothing => String::new(),
+        Complete::Files => String::from(" -F"),
+        Complete::Dirs => String::from(" -a '(__fish_complete_directories)'"),
+        Complete::Words(words) => format!(" -a {}", fish_quote(&words.join(" "))),
+        Complete::Tags | Complete::TagList => format!(" -a '({function}_tags)'"),
+    }
+}
+
+fn fish(program: &str, commands: &[(&str, Complete)], opt_check: &OptCheck) -> String {
+    let function = format!("_{}", function_name(program));
+    let mut s = format!("function {function}_command\n");
+    s.push_str("\tset -l tokens (commandline -opc)\n");
+    s.push_str("\tset -e tokens[1]\n");
+    s.push_str("\twhile set -q tokens[1]\n");
+    s.push_str("\t\tswitch $tokens[1]\n");
+    let arg_names = arg_names(opt_check);
+    if !arg_names.is_empty() {
+        s.push_str(&format!("\t\t\tcase {}\n\t\t\t\tset -e tokens[1]\n", arg_names.join(" ")));
+    }
+    s.push_str("\t\t\tcase '-*'\n");
+    s.push_str("\t\t\tcase '*'\n\t\t\t\techo $tokens[1]\n\t\t\t\treturn 0\n");
+    s.push_str("\t\tend\n\t\tset -e tokens[1]\n\tend\n\treturn 1\nend\n\n");
+
+    // Tags after the last comma of the current token keep what comes before it.
+    s.push_str(&format!("function {function}_tags\n"));
+    s.push_str("\tset -l prefix (string replace -r '[^,]*$' '' -- (commandline -ct))\n");
+    s.push_str(&format!("\tfor tag in ({program} list tags -o plain 2>/dev/null)\n"));
+    s.push_str("\t\techo $prefix$tag\n\tend\nend\n\n");
+
+    s.push_str(&format!("complete -c {program} -f\n"));
+    for (name, _) in commands {
+        s.push_str(&format!("complete -c {program} -n 'not {function}_command' -a {name}\n"));
+    }
+    for (k, opt) in opt_check {
+        let mut line = format!("complete -c {program}");
+        if !opt.commands.is_empty() {
+            line.push_str(&format!(" -n {}", fish_quote(&fish_command_is(&function, opt.commands))));
+        }
+        line.push_str(&format!(" -s {}", *k as char));
+        if !opt.long.is_empty() {
+            line.push_str(&format!(" -l {}", opt.long));
+        }
+        if matches!(opt.arg, OptArg::Mandatory(_)) {
+            line.push_str(" -r");
+            line.push_str(&fish_complete(&opt.complete, &function));
+        }
+        line.push_str(&format!(" -d {}", fish_quote(opt.help)));
+        s.push_str(&line);
+        s.push('\n');
+    }
+    for (name, complete) in commands {
+        let complete = fish_complete(complete, &function);
+        if !complete.is_empty() {
+            s.push_str(&format!("complete -c {program} -n {}{complete}\n", fish_quote(&fish_command_is(&function, &[name]))));
+        }
+    }
+    s
+}
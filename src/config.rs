@@ -0,0 +1,160 @@
+//! Defaults of the CLI options, from a config file and the environment.
+//!
+//! Each setting is taken from the first of: the command line, a `TAGINODE_*`
+//! environment variable, the config file, the built-in default. A list set at
+//! one level replaces the lists of the levels below instead of adding to them.
+//!
+//! The config file is `$TAGINODE_CONFIG`, else `$XDG_CONFIG_HOME/taginode/config`,
+//! else `~/.config/taginode/config`. It has one `key = value` per line, `#`
+//! starts a comment line, and list keys are repeated:
+//!
+//! | key            | environment             | default            |
+//! |----------------|-------------------------|--------------------|
+//! | `db`           | `TAGINODE_DB`           | `~/.taginode.db`   |
+//! | `search-root`  | `TAGINODE_SEARCH_ROOTS` | `.`                |
+//! | `cross-device` | `TAGINODE_CROSS_DEVICE` | `false`            |
+//! | `output`       | `TAGINODE_OUTPUT`       | `plain`            |
+//! | `ignore`       | `TAGINODE_IGNORE`       | none               |
+//! | `ignore-file`  | `TAGINODE_IGNORE_FILES` | `.taginodeignore`  |
+//!
+//! List variables are separated by `:`, like `PATH`. See
+//! [`WalkOptions`](crate::walk::WalkOptions) for the ignore patterns and files.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// None if neither set nor `HOME` known.
+    pub db: Option<String>,
+    /// Where search walks when given no directory.
+    pub search_roots: Vec<String>,
+    /// Whether walks enter other devices than their root's.
+    pub cross_device: bool,
+    pub output: String,
+    /// Patterns of entries that walks leave out, see [`WalkOptions::exclude`](crate::walk::WalkOptions::exclude).
+    pub ignore: Vec<String>,
+    /// Names of the ignore files walks read in each directory.
+    pub ignore_files: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            db: None,
+            search_roots: vec![String::from(".")],
+            cross_device: false,
+            output: String::from("plain"),
+            ignore: Vec::new(),
+            ignore_files: vec![String::from(".taginodeignore")],
+        }
+    }
+}
+
+impl Config {
+    /// The built-in defaults, overridden by the config file if there is one,
+    /// then by the environment.
+    pub fn load() -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = Config::path() {
+            match fs::read_to_string(&path) {
+                Ok(text) => config.parse(&text, &path.to_string_lossy())?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+        config.apply_env(|name| env::var(name).ok())?;
+        if config.db.is_none() {
+            config.db = env::var("HOME").ok().filter(|home| !home.is_empty())
+                .map(|home| format!("{home}/.taginode.db"));
+        }
+        Ok(config)
+    }
+
+    /// Where [`Config::load`] reads the config file.
+    pub fn path() -> Option<PathBuf> {
+        let var = |name| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+        if let Some(path) = var("TAGINODE_CONFIG") {
+            return Some(path)
+        }
+        let dir = var("XDG_CONFIG_HOME").or_else(|| Some(var("HOME")?.join(".config")))?;
+        Some(dir.join("taginode").join("config"))
+    }
+
+    /// Applies the settings of config file `text`; `origin` names it in errors.
+    pub fn parse(&mut self, text: &str, origin: &str) -> Result<()> {
+        let mut lists_seen = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            let invalid = |msg: &str| invalid(format!("{origin}:{}: {msg}", i + 1));
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(invalid("expected key = value"));
+            };
+            let (key, value) = (key.trim(), value.trim().to_string());
+            match key {
+                "db" => self.db = Some(value),
+                "search-root" => push(&mut self.search_roots, &mut lists_seen, key, value),
+                "cross-device" => self.cross_device = parse_bool(&value).ok_or_else(|| invalid("expected true or false"))?,
+                "output" => self.output = value,
+                "ignore" => push(&mut self.ignore, &mut lists_seen, key, value),
+                "ignore-file" => push(&mut self.ignore_files, &mut lists_seen, key, value),
+                _ => return Err(invalid(&format!("unknown key '{key}'"))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the `TAGINODE_*` variables that `var` finds set and not empty.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let var = |name| var(name).filter(|value: &String| !value.is_empty());
+        let list = |value: String| value.split(':').filter(|s| !s.is_empty()).map(String::from).collect();
+        if let Some(value) = var("TAGINODE_DB") {
+            self.db = Some(value);
+        }
+        if let Some(value) = var("TAGINODE_SEARCH_ROOTS") {
+            self.search_roots = list(value);
+        }
+        if let Some(value) = var("TAGINODE_CROSS_DEVICE") {
+            self.cross_device = parse_bool(&value)
+                .ok_or_else(|| invalid(format!("TAGINODE_CROSS_DEVICE: expected true or false, got '{value}'")))?;
+        }
+        if let Some(value) = var("TAGINODE_OUTPUT") {
+            self.output = value;
+        }
+        if let Some(value) = var("TAGINODE_IGNORE") {
+            self.ignore = list(value);
+        }
+        if let Some(value) = var("TAGINODE_IGNORE_FILES") {
+            self.ignore_files = list(value);
+        }
+        Ok(())
+    }
+}
+
+// The first value of a list key replaces the list set before.
+fn push<'a>(list: &mut Vec<String>, lists_seen: &mut Vec<&'a str>, key: &'a str, value: String) {
+    if !lists_seen.contains(&key) {
+        lists_seen.push(key);
+        list.clear();
+    }
+    list.push(value);
+}
+
+/// `true`, `yes`, `1` or `false`, `no`, `0`.
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
@@ -0,0 +1,165 @@
+//! Tagging by content: files are identified by their md5 and sha256 instead
+//! of an inode, so tags survive copies, save-by-rename and moves across
+//! filesystems.
+
+use std::fs;
+use std::io::Read;
+use std::path;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use sqlite3::Connection;
+use sqlite3::Value;
+use crate::{check_tag_names, prune_tags, transaction, File, Query, Result, MAX_SQL_ARGS};
+
+/// Reads the file at `path` and returns its content hashes.
+pub fn hash(path: &str) -> Result<File> {
+    let mut f = fs::File::open(path)?;
+    let (mut md5, mut sha256) = (Md5::new(), Sha256::new());
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 { break }
+        md5.update(&buf[..n]);
+        sha256.update(&buf[..n]);
+    }
+    Ok(File {
+        md5: hex(&md5.finalize()),
+        sha256: hex(&sha256.finalize()),
+        path: path.to_string(),
+    })
+}
+
+/// Tags each file's content with every tag in `tag_names`. The path stored
+/// for a content is the last one it was tagged at, made absolute.
+pub fn add(connection: &Connection, files: &[File], tag_names: &[&str]) -> Result<()> {
+    if files.is_empty() || tag_names.is_empty() { return Ok(()) }
+    check_tag_names(tag_names)?;
+    transaction(connection, || {
+        {
+            let mut cursor = connection
+                .prepare("INSERT OR IGNORE INTO tags(name) VALUES(?)")?
+                .cursor();
+            for tag_name in tag_names {
+                cursor.bind(&[Value::String(tag_name.to_string())])?;
+                while cursor.next()?.is_some() {}
+            }
+        }
+        {
+            let mut cursor = connection
+                .prepare("
+                    INSERT INTO file(md5, sha256, path) VALUES(?, ?, ?)
+                    ON CONFLICT(sha256) DO UPDATE SET path = excluded.path
+                    ")?
+                .cursor();
+            for file in files {
+                let path = path::absolute(&file.path)?;
+                cursor.bind(&[
+                    Value::String(file.md5.clone()),
+                    Value::String(file.sha256.clone()),
+                    Value::String(path.to_string_lossy().into_owned()),
+                ])?;
+                while cursor.next()?.is_some() {}
+            }
+        }
+        {
+            let mut cursor = connection
+                .prepare("
+                    INSERT OR IGNORE INTO relation_tag_file(tag_id, file_id)
+                    SELECT a.id, b.id FROM tags a, file b WHERE a.name = ? AND b.sha256 = ?
+                    ")?
+                .cursor();
+            for file in files {
+                for tag_name in tag_names {
+                    cursor.bind(&[
+                        Value::String(tag_name.to_string()),
+                        Value::String(file.sha256.clone()),
+                    ])?;
+                    while cursor.next()?.is_some() {}
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Removes every tag in `tag_names` from the content of every file in `files`.
+/// With `prune`, tags that are no longer attached to anything are deleted too.
+pub fn remove(connection: &Connection, files: &[File], tag_names: &[&str], prune: bool) -> Result<()> {
+    if files.is_empty() || tag_names.is_empty() { return Ok(()) }
+    let batch = MAX_SQL_ARGS.saturating_sub(tag_names.len()).max(1);
+    transaction(connection, || {
+        for files in files.chunks(batch) {
+            let sql_str = format!(
+                "
+                DELETE FROM relation_tag_file
+                WHERE tag_id IN (SELECT id FROM tags WHERE name IN ({}))
+                AND file_id IN (SELECT id FROM file WHERE sha256 IN ({}));
+                ",
+                vec!["?"; tag_names.len()].join(","),
+                vec!["?"; files.len()].join(","),
+            );
+            let mut sql_args = Vec::new();
+            for tag_name in tag_names {
+                sql_args.push(Value::String(tag_name.to_string()));
+            }
+            for file in files {
+                sql_args.push(Value::String(file.sha256.clone()));
+            }
+            let mut cursor = connection
+                .prepare(&sql_str)?
+                .cursor();
+            cursor.bind(&sql_args)?;
+            while cursor.next()?.is_some() {}
+        }
+        if prune {
+            prune_tags(connection, tag_names)?;
+        }
+        Ok(())
+    })
+}
+
+/// Returns the contents whose tags satisfy `query`.
+pub fn search(connection: &Connection, query: &Query) -> Result<Vec<File>> {
+    let mut sql_str = String::from("SELECT md5, sha256, path FROM file WHERE ");
+    let mut sql_args = Vec::new();
+    query.to_sql("relation_tag_file", "file_id", &mut sql_str, &mut sql_args);
+    let mut cursor = connection
+        .prepare(&sql_str)?
+        .cursor();
+    cursor.bind(&sql_args)?;
+
+    let mut files = Vec::new();
+    while let Some(row) = cursor.next()? {
+        files.push(File {
+            md5: row[0].as_string().unwrap_or_default().to_owned(),
+            sha256: row[1].as_string().unwrap_or_default().to_owned(),
+            path: row[2].as_string().unwrap_or_default().to_owned(),
+        });
+    }
+    Ok(files)
+}
+
+/// Returns the tags of the content of `file`.
+pub fn get_tags(connection: &Connection, file: &File) -> Result<Vec<String>> {
+    let sql_str =
+    "SELECT DISTINCT b.name FROM relation_tag_file a
+    JOIN tags b ON b.id = a.tag_id
+    JOIN file c ON c.id = a.file_id
+    WHERE c.sha256 = ?
+    ORDER BY a.id";
+    let mut cursor = connection
+        .prepare(sql_str)?
+        .cursor();
+    cursor.bind(&[Value::String(file.sha256.clone())])?;
+    let mut tag_names = Vec::new();
+    while let Some(row) = cursor.next()? {
+        if let Some(name) = row[0].as_string() {
+            tag_names.push(name.to_owned());
+        }
+    }
+    Ok(tag_names)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the taginode library.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying database failed (locked, read-only, constraint...),
+    /// with the SQLite result code if there is one.
+    Database { code: Option<isize>, message: String },
+    /// A tag name was rejected before it reached the database.
+    InvalidTag(String),
+    /// A tag with this name already exists.
+    TagExists(String),
+    /// A search expression could not be parsed.
+    InvalidQuery(String),
+    /// A tag, inode or file the caller referred to does not exist.
+    NotFound(String),
+    /// An I/O error, e.g. while reading file metadata.
+    Io(io::Error),
+    /// The database does not have the layout this version expects.
+    SchemaMismatch(String),
+    /// The storage behind a store cannot do this, e.g. content tagging in memory.
+    Unsupported(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Database { code: Some(code), message } => write!(f, "database error: {message} (code {code})"),
+            Error::Database { code: None, message } => write!(f, "database error: {message}"),
+            Error::InvalidTag(name) => write!(f, "invalid tag name: {name:?}"),
+            Error::TagExists(name) => write!(f, "tag already exists: {name:?}"),
+            Error::InvalidQuery(msg) => write!(f, "invalid query: {msg}"),
+            Error::NotFound(what) => write!(f, "not found: {what}"),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::SchemaMismatch(msg) => write!(f, "schema mismatch: {msg}"),
+            Error::Unsupported(what) => write!(f, "not supported by this storage: {what}"),
+        }
+    }
+}
+
+impl Error {
+    /// Another connection holds a lock the operation needed; trying again
+    /// later may succeed.
+    pub fn is_busy(&self) -> bool {
+        // SQLITE_BUSY and SQLITE_LOCKED
+        matches!(self, Error::Database { code: Some(5 | 6), .. })
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Only for `?` inside the crate: sqlite3 is not part of the API.
+#[doc(hidden)]
+impl From<sqlite3::Error> for Error {
+    fn from(err: sqlite3::Error) -> Self {
+        Error::Database { code: err.code, message: err.message.unwrap_or_else(|| String::from("an SQLite error")) }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
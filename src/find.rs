@@ -0,0 +1,181 @@
+//! Finding where tagged files are by walking directories, for the inodes and
+//! contents whose paths are not known.
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{self, Path};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::{content, walk, Error, File, INode};
+use crate::walk::WalkOptions;
+
+/// What [`Find`] looks for.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    /// Tagged inodes, by device and then by number.
+    Inodes(&'a HashMap<u64, HashMap<u64, INode>>),
+    /// The sha256 of tagged contents.
+    Contents(&'a HashSet<String>),
+}
+
+/// What [`Find::run`] reports as it walks.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A file that is looked for, with its content for [`Target::Contents`].
+    Found(&'a str, INode, Option<File>),
+    /// A path of a file already walked at the second path; it is not entered.
+    Duplicate(&'a str, &'a str),
+    Error(&'a str, Error),
+}
+
+/// A walk over directories for the files of a [`Target`].
+#[derive(Debug, Clone)]
+pub struct Find<'a> {
+    pub target: Target<'a>,
+    /// How to walk. For [`Target::Inodes`], devices that have tagged inodes
+    /// are always entered, and `cross_dev` enters the others too.
+    pub options: WalkOptions,
+    /// Report each path of a file instead of only the first one walked.
+    pub duplicates: bool,
+    /// Threads to walk on, see [`walk::walk_parallel`]. With one, entries come
+    /// in the order of [`walk::walk`].
+    pub threads: usize,
+}
+
+// What one run or located call keeps between entries.
+struct State<'a> {
+    // The first path of every file walked, by device and inode number.
+    seen: Mutex<HashMap<(u64, u64), String>>,
+    stopped: AtomicBool,
+    event: &'a (dyn Fn(Event) -> bool + Sync),
+}
+
+impl State<'_> {
+    fn new(event: &(dyn Fn(Event) -> bool + Sync)) -> State<'_> {
+        State { seen: Mutex::default(), stopped: AtomicBool::new(false), event }
+    }
+
+    fn report(&self, event: Event) {
+        if !(self.event)(event) {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Find<'_> {
+    /// Walks `roots`, passing what it finds to `event`, which returns whether
+    /// to go on. Returns how many entries the walk rules left out.
+    pub fn run(&self, roots: &[&str], event: &(dyn Fn(Event) -> bool + Sync)) -> usize {
+        let state = State::new(event);
+        let visit = |path: &str, metadata: &Metadata| self.visit(&state, path, metadata);
+        let error = |path: &str, err| state.report(Event::Error(path, Error::Io(err)));
+        let options = self.walk_options();
+        if self.threads > 1 {
+            return walk::walk_parallel(roots, &options, self.threads, &visit, &error)
+        }
+        roots.iter().map(|root| walk::walk(root, &options, &mut |path, metadata| visit(path, metadata), &mut |path, err| error(path, err))).sum()
+    }
+
+    /// Like [`Find::run`], but instead of walking looks only at `paths`,
+    /// absolute ones found some other way such as the path index. Those that
+    /// a run would not come across, not being under one of `roots` or being
+    /// left out by the walk rules, are passed over. The others are reported
+    /// under the first root they are in, spelled as given like a run does.
+    pub fn located(&self, roots: &[&str], paths: &[&str], event: &(dyn Fn(Event) -> bool + Sync)) -> io::Result<()> {
+        let options = self.walk_options();
+        let mut matchers = Vec::new();
+        for root in roots {
+            let absolute = path::absolute(root)?;
+            match walk::Matcher::new(&absolute.to_string_lossy(), &options) {
+                Ok(matcher) => matchers.push((root, absolute, matcher)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err),
+            }
+        }
+        let state = State::new(event);
+        for path in paths {
+            if state.stopped.load(Ordering::Relaxed) {
+                break
+            }
+            let under: Vec<_> = matchers.iter()
+                .filter_map(|(root, absolute, matcher)| Some((root, Path::new(path).strip_prefix(absolute).ok()?, matcher)))
+                .collect();
+            if under.is_empty() {
+                continue
+            }
+            let metadata = match fs::symlink_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    state.report(Event::Error(path, Error::Io(err)));
+                    continue
+                },
+            };
+            let walked = under.iter().find(|(_, rel, matcher)| {
+                matcher.matches(&rel.to_string_lossy(), rel.components().count(), metadata.is_dir())
+            });
+            if let Some((root, rel, _)) = walked {
+                let path = if rel.as_os_str().is_empty() { Path::new(root).to_path_buf() } else { Path::new(root).join(rel) };
+                self.visit(&state, &path.to_string_lossy(), &metadata);
+            }
+        }
+        Ok(())
+    }
+
+    // visit leaves the devices without tagged inodes itself.
+    fn walk_options(&self) -> WalkOptions {
+        match self.target {
+            Target::Inodes(_) => WalkOptions { cross_dev: true, ..self.options.clone() },
+            Target::Contents(_) => self.options.clone(),
+        }
+    }
+
+    // Reports path if it is looked for, and returns whether to enter it.
+    fn visit(&self, state: &State, path: &str, metadata: &Metadata) -> bool {
+        if state.stopped.load(Ordering::Relaxed) {
+            return false
+        }
+        if !self.duplicates {
+            let mut seen = state.seen.lock().unwrap_or_else(|err| err.into_inner());
+            match seen.entry((metadata.dev(), metadata.ino())) {
+                Entry::Occupied(first) => {
+                    let first = first.get().clone();
+                    drop(seen);
+                    state.report(Event::Duplicate(path, &first));
+                    return false
+                },
+                Entry::Vacant(entry) => {
+                    entry.insert(path.to_string());
+                },
+            }
+        }
+        match self.target {
+            Target::Inodes(inodes) => match inodes.get(&metadata.dev()) {
+                Some(inode_map) => {
+                    if let Some(tagged) = inode_map.get(&metadata.ino()) {
+                        let inode = INode::from_metadata(metadata);
+                        if tagged.same_file(&inode) {
+                            state.report(Event::Found(path, inode, None));
+                        }
+                    }
+                    true
+                },
+                None => self.options.cross_dev,
+            },
+            Target::Contents(hashes) => {
+                if metadata.is_file() {
+                    match content::hash(path) {
+                        Ok(file) if hashes.contains(&file.sha256) => {
+                            state.report(Event::Found(path, INode::from_metadata(metadata), Some(file)));
+                        },
+                        Ok(_) => (),
+                        Err(err) => state.report(Event::Error(path, err)),
+                    }
+                }
+                true
+            },
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Garbage collection of inode rows whose files are gone.
+
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use sqlite3::{Connection, Value};
+use crate::{sql, transaction, walk, INode, Result, MAX_SQL_ARGS};
+use crate::walk::WalkOptions;
+
+/// What [`gc`] found, and removed unless it was a dry run.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Entries looked at during the walk.
+    pub scanned: usize,
+    /// Entries left out of the walk, see [`walk::walk`].
+    pub skipped: usize,
+    /// Tagged inodes that no longer exist.
+    pub missing: Vec<INode>,
+    /// Tagged inode numbers now used by another file (the btime changed).
+    pub reused: Vec<INode>,
+    /// Tag relations belonging to the inodes above.
+    pub relations: usize,
+}
+
+/// Walks `roots` (each staying on its own device) and finds the tagged inodes
+/// on them that are stale. Only walks without errors and without anything
+/// left out by the rules of `options` count for missing inodes, since
+/// otherwise part of the tree was not seen. A walk from a mount point finds
+/// every inode of its device that is missing; one from another directory
+/// only those whose recorded paths all lie under it, so a file moved out of
+/// such a root since its paths were recorded is taken for missing too. Unless
+/// `dry_run`, stale inodes are deleted along with their tag relations and
+/// recorded paths.
+pub fn gc(connection: &Connection, roots: &[&str], options: &WalkOptions, dry_run: bool) -> Result<Report> {
+    let mut known: HashMap<(u64, u64), (i64, INode)> = HashMap::new();
+    {
+        let sql_str = "SELECT id, device, number, CAST(strftime('%s', btime) AS INT) FROM inodes";
+        let mut rows = sql::Rows::new(connection, sql_str, &[])?;
+        while let Some(row) = rows.next()? {
+            let inode = INode {
+                device: sql::from_int(&row[1]),
+                number: sql::from_int(&row[2]),
+                btime: row[3].as_integer().map(|v| v as u64),
+            };
+            known.insert((inode.device, inode.number), (row[0].as_integer().unwrap_or(0), inode));
+        }
+    }
+
+    let mut report = Report::default();
+    let mut seen = HashSet::new();
+    let mut complete = HashSet::new();
+    let mut incomplete = HashSet::new();
+    // Directories other than mount points walked completely, as recorded
+    // paths would spell them.
+    let mut complete_dirs = Vec::new();
+    let mut reused_ids = Vec::new();
+    for root in roots {
+        let root_dev = match std::fs::symlink_metadata(root) {
+            Ok(metadata) => metadata.dev(),
+            Err(_) => continue,
+        };
+        let mount_point = is_mount_point(root, root_dev);
+        if mount_point {
+            complete.insert(root_dev);
+        }
+        let mut failed = false;
+        let options = WalkOptions { cross_dev: false, ..options.clone() };
+        let skipped = walk::walk(root, &options,
+            &mut |_, metadata| {
+                report.scanned += 1;
+                let key = (metadata.dev(), metadata.ino());
+                if let Some((id, inode)) = known.get(&key) {
+                    if seen.insert(key) && !inode.same_file(&INode::from_metadata(metadata)) {
+                        reused_ids.push(*id);
+                        report.reused.push(inode.clone());
+                    }
+                }
+                true
+            },
+            &mut |_, _| failed = true,
+        );
+        if failed || skipped > 0 {
+            incomplete.insert(root_dev);
+        } else if !mount_point {
+            complete_dirs.extend(std::path::absolute(root));
+            complete_dirs.extend(std::fs::canonicalize(root));
+        }
+        report.skipped += skipped;
+    }
+
+    // Whether all recorded paths of each inode lie under complete_dirs.
+    let mut under_dirs: HashMap<i64, bool> = HashMap::new();
+    if !complete_dirs.is_empty() {
+        let mut rows = sql::Rows::new(connection, "SELECT inode_id, path FROM paths", &[])?;
+        while let Some(row) = rows.next()? {
+            let path = row[1].as_string().unwrap_or_default();
+            let under = complete_dirs.iter().any(|dir| Path::new(path).starts_with(dir));
+            *under_dirs.entry(row[0].as_integer().unwrap_or(0)).or_insert(true) &= under;
+        }
+    }
+
+    let mut ids = reused_ids;
+    for (key, (id, inode)) in &known {
+        let walked = if complete.contains(&key.0) {
+            !incomplete.contains(&key.0)
+        } else {
+            under_dirs.get(id).copied().unwrap_or(false)
+        };
+        if walked && !seen.contains(key) {
+            ids.push(*id);
+            report.missing.push(inode.clone());
+        }
+    }
+
+    transaction(connection, || {
+        for ids in ids.chunks(MAX_SQL_ARGS) {
+            let holders = vec!["?"; ids.len()].join(",");
+            let sql_args: Vec<Value> = ids.iter().map(|id| Value::Integer(*id)).collect();
+            let sql_str = format!("SELECT COUNT(*) FROM relation_tag_inode WHERE inode_id IN ({holders})");
+            let mut cursor = connection.prepare(&sql_str)?.cursor();
+            cursor.bind(&sql_args)?;
+            if let Some(row) = cursor.next()? {
+                report.relations += row[0].as_integer().unwrap_or(0) as usize;
+            }
+            if dry_run { continue }
+            // relations and paths go with the inode by ON DELETE CASCADE
+            let sql_str = format!("DELETE FROM inodes WHERE id IN ({holders})");
+            let mut cursor = connection.prepare(&sql_str)?.cursor();
+            cursor.bind(&sql_args)?;
+            while cursor.next()?.is_some() {}
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+// A root covers its whole device if it is where that device is mounted.
+fn is_mount_point(root: &str, root_dev: u64) -> bool {
+    match std::fs::canonicalize(root) {
+        Ok(path) => match path.parent() {
+            None => true,
+            Some(parent) => std::fs::symlink_metadata(parent).is_ok_and(|m| m.dev() != root_dev),
+        },
+        Err(_) => false,
+    }
+}
+
+/// The mount points of the devices that have tagged inodes, for running
+/// [`gc`] over everything.
+pub fn tagged_mount_points(connection: &Connection) -> Result<Vec<String>> {
+    let mut devices = HashSet::new();
+    let mut cursor = connection.prepare("SELECT DISTINCT device FROM inodes")?.cursor();
+    while let Some(row) = cursor.next()? {
+        devices.insert(sql::from_int(&row[0]));
+    }
+    let mut roots = Vec::new();
+    for mount_point in walk::mount_points()? {
+        if let Ok(metadata) = std::fs::symlink_metadata(Path::new(&mount_point)) {
+            if devices.remove(&metadata.dev()) {
+                roots.push(mount_point);
+            }
+        }
+    }
+    Ok(roots)
+}
@@ -0,0 +1,125 @@
+//! Last known paths of tagged inodes, so a search can stat a handful of
+//! candidate paths instead of walking a whole directory tree.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path;
+use sqlite3::Connection;
+use sqlite3::Value;
+use crate::{sql, transaction, INode, Result, MAX_SQL_ARGS};
+
+/// An inode and a path it was seen at.
+pub type PathEntry = (INode, String);
+
+/// Remembers that each inode was seen at the given path. Paths are stored
+/// absolute; inodes that are not tagged are ignored.
+pub fn record(connection: &Connection, entries: &[PathEntry]) -> Result<()> {
+    transaction(connection, || {
+        let sql_str = "
+            INSERT INTO paths(inode_id, path)
+            SELECT id, ? FROM inodes WHERE device = ? AND number = ?
+            ON CONFLICT(inode_id, path) DO UPDATE SET update_at = CURRENT_TIMESTAMP
+            ";
+        let mut cursor = connection
+            .prepare(sql_str)?
+            .cursor();
+        for (inode, path) in entries {
+            let path = path::absolute(path)?;
+            cursor.bind(&[
+                Value::String(path.to_string_lossy().into_owned()),
+                Value::Integer(sql::to_int(inode.device)),
+                Value::Integer(sql::to_int(inode.number)),
+            ])?;
+            while cursor.next()?.is_some() {}
+        }
+        Ok(())
+    })
+}
+
+/// All recorded paths of `inode`.
+pub fn paths(connection: &Connection, inode: &INode) -> Result<Vec<String>> {
+    let sql_str = "
+        SELECT a.path FROM paths a
+        JOIN inodes b ON a.inode_id = b.id
+        WHERE b.device = ? AND b.number = ?
+        ORDER BY a.path
+        ";
+    let mut cursor = connection
+        .prepare(sql_str)?
+        .cursor();
+    cursor.bind(&[
+        Value::Integer(sql::to_int(inode.device)),
+        Value::Integer(sql::to_int(inode.number)),
+    ])?;
+    let mut paths = Vec::new();
+    while let Some(row) = cursor.next()? {
+        if let Some(path) = row[0].as_string() {
+            paths.push(path.to_owned());
+        }
+    }
+    Ok(paths)
+}
+
+/// Stats the recorded paths of `inodes` and splits them into inodes found at
+/// one or more of their paths, and inodes none of whose paths still lead to
+/// them (moved, deleted or never recorded). Paths that went stale are dropped
+/// from the index.
+pub fn locate(connection: &Connection, inodes: &[INode]) -> Result<(Vec<PathEntry>, Vec<INode>)> {
+    let (mut found, mut stale) = (Vec::new(), Vec::new());
+    let mut stale_ids = Vec::new();
+    for inodes in inodes.chunks(MAX_SQL_ARGS / 2) {
+        let sql_str = format!(
+            "
+            SELECT b.device, b.number, a.path, a.id FROM paths a
+            JOIN inodes b ON a.inode_id = b.id
+            WHERE (b.device, b.number) IN (VALUES({}))
+            ",
+            vec!["?,?"; inodes.len()].join("), ("),
+        );
+        let mut sql_args = Vec::new();
+        for inode in inodes {
+            sql_args.push(Value::Integer(sql::to_int(inode.device)));
+            sql_args.push(Value::Integer(sql::to_int(inode.number)));
+        }
+        let mut cursor = connection
+            .prepare(&sql_str)?
+            .cursor();
+        cursor.bind(&sql_args)?;
+
+        let by_key: HashMap<(u64, u64), &INode> = inodes.iter().map(|inode| {
+            ((inode.device, inode.number), inode)
+        }).collect();
+        let mut located = HashSet::new();
+        while let Some(row) = cursor.next()? {
+            let key = (sql::from_int(&row[0]), sql::from_int(&row[1]));
+            let path = row[2].as_string().unwrap_or_default();
+            let Some(inode) = by_key.get(&key) else { continue };
+            match fs::symlink_metadata(path) {
+                Ok(metadata) if inode.same_file(&INode::from_metadata(&metadata)) => {
+                    located.insert(key);
+                    found.push(((*inode).clone(), path.to_owned()));
+                },
+                _ => stale_ids.push(row[3].as_integer().unwrap_or(0)),
+            }
+        }
+        for inode in inodes {
+            if !located.contains(&(inode.device, inode.number)) {
+                stale.push(inode.clone());
+            }
+        }
+    }
+
+    transaction(connection, || {
+        for ids in stale_ids.chunks(MAX_SQL_ARGS) {
+            let sql_str = format!("DELETE FROM paths WHERE id IN ({})", vec!["?"; ids.len()].join(","));
+            let sql_args: Vec<Value> = ids.iter().map(|id| Value::Integer(*id)).collect();
+            let mut cursor = connection
+                .prepare(&sql_str)?
+                .cursor();
+            cursor.bind(&sql_args)?;
+            while cursor.next()?.is_some() {}
+        }
+        Ok(())
+    })?;
+    Ok((found, stale))
+}
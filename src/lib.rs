@@ -1,26 +1,72 @@
 pub mod sql;
 pub mod opt;
+pub mod completion;
+pub mod config;
+pub mod output;
+pub mod query;
+pub mod index;
+pub mod content;
+pub mod walk;
+pub mod find;
+pub mod gc;
+pub mod store;
+pub mod storage;
+pub mod memory;
+mod error;
+
+pub use error::{Error, Result};
+pub use query::Query;
+pub use store::{Stats, TagStore};
+pub use storage::Storage;
 
 use std::collections::HashSet;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::time::UNIX_EPOCH;
 use sqlite3::Connection;
 use sqlite3::Value;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct INode {
     pub device: u64,
     pub number: u64,
     pub btime: Option<u64>,
 }
 
+impl INode {
+    /// The inode `metadata` was read from. btime is None where the
+    /// filesystem does not report a birth time.
+    pub fn from_metadata(metadata: &Metadata) -> INode {
+        INode {
+            device: metadata.dev(),
+            number: metadata.ino(),
+            btime: metadata.created().ok()
+                .and_then(|btime| btime.duration_since(UNIX_EPOCH).ok())
+                .map(|btime| btime.as_secs()),
+        }
+    }
+
+    /// Same device and number, and the same btime when both sides know it.
+    pub fn same_file(&self, other: &INode) -> bool {
+        self.device == other.device && self.number == other.number &&
+        (self.btime.is_none() || other.btime.is_none() || self.btime == other.btime)
+    }
+}
+
+/// A file identified by its content, see [`content`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct File {
     pub md5: String,
     pub sha256: String,
     pub path: String,
 }
 
-// pub struct Tag {
-//     pub Name: String,
-// }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// Number of inodes tagged with it.
+    pub inode_num: u64,
+}
 
 // pub struct FileTags {
 //     inode: INode,
@@ -28,14 +74,51 @@ pub struct File {
 //     tags: Vec<Tag>,
 // }
 
-pub fn get_inodes(connection: &Connection, tag_names: &[&str]) -> Vec<INode> {
-    let mut h = HashSet::new();
-    for tag_name in tag_names {
-        h.insert(tag_name);
+/// Results read from the storage one at a time, see the `*_iter` functions.
+/// Borrows the connection or storage until dropped.
+pub struct Iter<'l, T> {
+    inner: Box<dyn Iterator<Item = Result<T>> + 'l>,
+}
+
+impl<'l, T> Iter<'l, T> {
+    pub fn new(inner: impl Iterator<Item = Result<T>> + 'l) -> Iter<'l, T> {
+        Iter { inner: Box::new(inner) }
     }
-    let tag_names: Vec<&&str> = h.into_iter().collect();
 
-    let mut inodes = Vec::new();
+    // Reads each row of `rows` with `read`.
+    fn rows(mut rows: sql::Rows<'l>, read: fn(Vec<Value>) -> T) -> Iter<'l, T> where T: 'l {
+        Iter::new(std::iter::from_fn(move || rows.next().transpose().map(|row| row.map(read))))
+    }
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.inner.next()
+    }
+}
+
+fn read_inode(row: Vec<Value>) -> INode {
+    INode {
+        device: sql::from_int(&row[0]),
+        number: sql::from_int(&row[1]),
+        btime: row[2].as_integer().map(|v| v as u64),
+    }
+}
+
+fn read_name(row: Vec<Value>) -> String {
+    row[0].as_string().unwrap_or_default().to_owned()
+}
+
+/// Returns the inodes tagged with every tag in `tag_names`.
+pub fn get_inodes(connection: &Connection, tag_names: &[&str]) -> Result<Vec<INode>> {
+    get_inodes_iter(connection, tag_names)?.collect()
+}
+
+/// Like [`get_inodes`], reading the inodes as the iterator advances.
+pub fn get_inodes_iter<'l>(connection: &'l Connection, tag_names: &[&str]) -> Result<Iter<'l, INode>> {
+    let tag_names: HashSet<&str> = tag_names.iter().copied().collect();
     let sql_str = format!(
         "
         SELECT DISTINCT b.device, b.number, CAST(strftime('%s', b.btime) AS INT) as btime
@@ -51,28 +134,85 @@ pub fn get_inodes(connection: &Connection, tag_names: &[&str]) -> Vec<INode> {
     let sql_args: Vec<Value> = tag_names.iter().map(|&val| {
         Value::String(val.to_string())
     }).collect();
-    let mut cursor = connection
-        .prepare(&sql_str)
-        .unwrap()
-        .cursor();
-    cursor.bind(&sql_args).unwrap();
+    Ok(Iter::rows(sql::Rows::new(connection, &sql_str, &sql_args)?, read_inode))
+}
 
-    while let Some(row) = cursor.next().unwrap() {
-        let btime = match row[2].as_integer() {
-            Some(v) => Some(v as u64),
-            None => None,
-        };
-        inodes.push(INode {
-            device: row[0].as_integer().unwrap() as u64,
-            number: row[1].as_integer().unwrap() as u64,
-            btime,
-        });
+/// Returns the inodes whose tags satisfy `query`, see [`query::parse`].
+pub fn search(connection: &Connection, query: &Query) -> Result<Vec<INode>> {
+    search_iter(connection, query)?.collect()
+}
+
+/// Like [`search`], reading the inodes as the iterator advances.
+pub fn search_iter<'l>(connection: &'l Connection, query: &Query) -> Result<Iter<'l, INode>> {
+    let mut sql_str = String::from("
+        SELECT device, number, CAST(strftime('%s', btime) AS INT) as btime
+        FROM inodes WHERE ");
+    let mut sql_args = Vec::new();
+    query.to_sql("relation_tag_inode", "inode_id", &mut sql_str, &mut sql_args);
+    Ok(Iter::rows(sql::Rows::new(connection, &sql_str, &sql_args)?, read_inode))
+}
+
+/// Runs `f` in a transaction: everything it writes is committed together, or
+/// rolled back if it returns an error. Every mutating function here is atomic
+/// on its own; since calls nest, wrapping many of them in one `transaction`
+/// also makes them share a single, much cheaper, commit.
+///
+/// The outermost transaction takes the write lock up front, waiting for
+/// other writers, so it cannot fail halfway with a busy error.
+pub fn transaction<T>(connection: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let nested = begin(connection)?;
+    match f() {
+        Ok(ret) => {
+            commit(connection, nested)?;
+            Ok(ret)
+        },
+        Err(err) => {
+            rollback(connection, nested)?;
+            Err(err)
+        },
+    }
+}
+
+// Starts a transaction, or a savepoint if one is open already, which BEGIN
+// fails on. Returns whether it nested.
+pub(crate) fn begin(connection: &Connection) -> Result<bool> {
+    match sql::retry(|| Ok(connection.execute("BEGIN IMMEDIATE")?)) {
+        Ok(()) => Ok(false),
+        Err(err) if err.is_busy() => Err(err),
+        Err(_) => {
+            connection.execute("SAVEPOINT taginode")?;
+            Ok(true)
+        },
     }
-    return inodes;
 }
 
-pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) {
-    if inodes.len() == 0 || tag_names.len() == 0 { return }
+pub(crate) fn commit(connection: &Connection, nested: bool) -> Result<()> {
+    let sql_str = if nested { "RELEASE taginode" } else { "COMMIT" };
+    sql::retry(|| Ok(connection.execute(sql_str)?))
+}
+
+pub(crate) fn rollback(connection: &Connection, nested: bool) -> Result<()> {
+    let sql_str = if nested { "ROLLBACK TO taginode; RELEASE taginode" } else { "ROLLBACK" };
+    Ok(connection.execute(sql_str)?)
+}
+
+// SQLite limits the number of host parameters in one statement (999 in older
+// builds), so big inode lists are written in batches.
+pub(crate) const MAX_SQL_ARGS: usize = 999;
+
+pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
+    if inodes.is_empty() || tag_names.is_empty() { return Ok(()) }
+    check_tag_names(tag_names)?;
+    let batch = (MAX_SQL_ARGS / 2 / tag_names.len()).max(1);
+    transaction(connection, || {
+        for inodes in inodes.chunks(batch) {
+            add_batch(connection, inodes, tag_names)?;
+        }
+        Ok(())
+    })
+}
+
+fn add_batch(connection: &Connection, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
     {
         let sql_str = format!(
             "
@@ -85,42 +225,42 @@ pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) {
             sql_args.push(Value::String(tag_name.to_string()));
         }
         let mut cursor = connection
-            .prepare(&sql_str)
-            .unwrap()
+            .prepare(&sql_str)?
             .cursor();
-        cursor.bind(&sql_args).unwrap();
-        while let Some(_) = cursor.next().unwrap() {}
+        cursor.bind(&sql_args)?;
+        while cursor.next()?.is_some() {}
     }
     {
-        let mut sqls: Vec<String> = Vec::new();
+        // ?1 device, ?2 number, ?3 btime or NULL. A known btime that differs
+        // means the inode number was reused: the old file's tags and paths
+        // are not the new one's. An unknown btime keeps the stored one.
+        let sql_strs = [
+            "DELETE FROM relation_tag_inode WHERE inode_id IN (
+                SELECT id FROM inodes WHERE device = ?1 AND number = ?2
+                AND btime IS NOT NULL AND btime != strftime('%Y-%m-%d %H:%M:%S', ?3, 'unixepoch'))",
+            "DELETE FROM paths WHERE inode_id IN (
+                SELECT id FROM inodes WHERE device = ?1 AND number = ?2
+                AND btime IS NOT NULL AND btime != strftime('%Y-%m-%d %H:%M:%S', ?3, 'unixepoch'))",
+            "INSERT OR IGNORE INTO inodes(device,number,btime)
+                VALUES(?1,?2,strftime('%Y-%m-%d %H:%M:%S', ?3, 'unixepoch'))",
+            "UPDATE inodes SET btime = strftime('%Y-%m-%d %H:%M:%S', ?3, 'unixepoch')
+                WHERE ?3 IS NOT NULL AND device = ?1 AND number = ?2",
+        ];
+        let mut cursors = Vec::new();
+        for sql_str in sql_strs {
+            cursors.push(connection.prepare(sql_str)?.cursor());
+        }
         for inode in inodes {
-            match inode.btime {
-                None => {
-                    sqls.push(format!("
-                        INSERT OR IGNORE INTO inodes(device,number) VALUES({},{});
-                        ",
-                        inode.device, inode.number,
-                    ));
-                },
-                Some(btime) => {
-                    sqls.push(format!("
-                    INSERT OR IGNORE INTO inodes(device,number,btime) 
-                    VALUES({},{},strftime('%Y-%m-%d %H:%M:%S', {}, 'unixepoch'));
-                    UPDATE inodes SET btime = strftime('%Y-%m-%d %H:%M:%S', {}, 'unixepoch') 
-                    WHERE  0 = (SELECT CHANGES()) AND device = {} AND number = {};
-                    ", 
-                    inode.device,
-                    inode.number,
-                    btime,
-                    btime,
-                    inode.device,
-                    inode.number,
-                    ));
-                }
+            let sql_args = [
+                Value::Integer(sql::to_int(inode.device)),
+                Value::Integer(sql::to_int(inode.number)),
+                inode.btime.map_or(Value::Null, |btime| Value::Integer(btime as i64)),
+            ];
+            for cursor in &mut cursors {
+                cursor.bind(&sql_args)?;
+                while cursor.next()?.is_some() {}
             }
         }
-        let sql_str = sqls.join("");
-        connection.execute(sql_str).unwrap();
     }
     let mut tag_ids = vec![0;0];
     {
@@ -135,12 +275,11 @@ pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) {
             sql_args.push(Value::String(tag_name.to_string()));
         }
         let mut cursor = connection
-            .prepare(&sql_str)
-            .unwrap()
+            .prepare(&sql_str)?
             .cursor();
-        cursor.bind(&sql_args).unwrap();
-        while let Some(row) = cursor.next().unwrap() {
-            tag_ids.push(row[0].as_integer().unwrap());
+        cursor.bind(&sql_args)?;
+        while let Some(row) = cursor.next()? {
+            tag_ids.push(row[0].as_integer().unwrap_or(0));
         }
     }
     let mut inode_ids = vec![0;0];
@@ -153,17 +292,16 @@ pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) {
         );
         let mut sql_args = Vec::new();
         for inode in inodes {
-            sql_args.push(Value::Integer(inode.device as i64));
-            sql_args.push(Value::Integer(inode.number as i64));
+            sql_args.push(Value::Integer(sql::to_int(inode.device)));
+            sql_args.push(Value::Integer(sql::to_int(inode.number)));
         }
         let mut cursor = connection
-            .prepare(&sql_str)
-            .unwrap()
+            .prepare(&sql_str)?
             .cursor();
-        cursor.bind(&sql_args).unwrap();
+        cursor.bind(&sql_args)?;
 
-        while let Some(row) = cursor.next().unwrap() {
-            inode_ids.push(row[0].as_integer().unwrap());
+        while let Some(row) = cursor.next()? {
+            inode_ids.push(row[0].as_integer().unwrap_or(0));
         }
     }
 
@@ -182,59 +320,239 @@ pub fn add(connection: &Connection, inodes: &[INode], tag_names: &[&str]) {
             }
         }
         let mut cursor = connection
-            .prepare(&sql_str)
-            .unwrap()
+            .prepare(&sql_str)?
             .cursor();
-        cursor.bind(&sql_args).unwrap();
-        while let Some(_) = cursor.next().unwrap() {}
+        cursor.bind(&sql_args)?;
+        while cursor.next()?.is_some() {}
     }
+    Ok(())
 }
 
-pub fn list_tags(connection: &Connection) -> Vec<String> {
-    let sql_str = "SELECT DISTINCT name FROM tags"; 
+/// Removes every tag in `tag_names` from every inode in `inodes`.
+/// With `prune`, tags that are no longer attached to anything are deleted too.
+pub fn remove(connection: &Connection, inodes: &[INode], tag_names: &[&str], prune: bool) -> Result<()> {
+    if inodes.is_empty() || tag_names.is_empty() { return Ok(()) }
+    let batch = (MAX_SQL_ARGS.saturating_sub(tag_names.len()) / 2).max(1);
+    transaction(connection, || {
+        for inodes in inodes.chunks(batch) {
+            remove_batch(connection, inodes, tag_names)?;
+        }
+        if prune {
+            prune_tags(connection, tag_names)?;
+        }
+        Ok(())
+    })
+}
+
+fn remove_batch(connection: &Connection, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
+    let sql_str = format!(
+        "
+        DELETE FROM relation_tag_inode 
+        WHERE tag_id IN (SELECT id FROM tags WHERE name IN ({})) 
+        AND inode_id IN (SELECT id FROM inodes WHERE (device, number) IN (VALUES({})));
+        ", 
+        vec!["?"; tag_names.len()].join(","),
+        vec!["?,?"; inodes.len()].join("), ("),
+    );
+    let mut sql_args = Vec::new();
+    for tag_name in tag_names {
+        sql_args.push(Value::String(tag_name.to_string()));
+    }
+    for inode in inodes {
+        sql_args.push(Value::Integer(sql::to_int(inode.device)));
+        sql_args.push(Value::Integer(sql::to_int(inode.number)));
+    }
     let mut cursor = connection
-        .prepare(&sql_str)
-        .unwrap()
+        .prepare(&sql_str)?
         .cursor();
+    cursor.bind(&sql_args)?;
+    while cursor.next()?.is_some() {}
+    Ok(())
+}
 
-    let mut tag_names = Vec::new();
-    while let Some(row) = cursor.next().unwrap() {
-        tag_names.push(row[0].as_string().unwrap().to_owned());
-    }
-    return tag_names;
+/// Renames tag `old` to `new`. Fails with [`Error::TagExists`] if `new` is
+/// already taken, use [`merge_tags`] to combine two tags instead.
+pub fn rename_tag(connection: &Connection, old: &str, new: &str) -> Result<()> {
+    check_tag_names(&[new])?;
+    // no other writer may create `new` between the check and the update
+    transaction(connection, || {
+        tag_id(connection, old)?;
+        if old == new { return Ok(()) }
+        if tag_id(connection, new).is_ok() {
+            return Err(Error::TagExists(new.to_string()));
+        }
+        let mut cursor = connection
+            .prepare("UPDATE tags SET name = ? WHERE name = ?")?
+            .cursor();
+        cursor.bind(&[Value::String(new.to_string()), Value::String(old.to_string())])?;
+        while cursor.next()?.is_some() {}
+        Ok(())
+    })
+}
+
+/// Moves everything tagged with any of `sources` to tag `dst`, creating it
+/// if needed, and deletes the source tags.
+pub fn merge_tags(connection: &Connection, sources: &[&str], dst: &str) -> Result<()> {
+    check_tag_names(&[dst])?;
+    transaction(connection, || merge_tags_into(connection, sources, dst))
 }
 
-pub fn get_tags(connection: &Connection, inode: INode) -> Vec<String> {
-    let sql_str = "SELECT id FROM `inodes` 
-    WHERE device = ? AND number = ? AND 
-    (CAST(strftime('%s', btime) AS INT) = ? OR btime IS NULL)";
-    let mut cursor = connection.prepare(&sql_str).unwrap().cursor();
-    let mut sql_args = vec![
-         Value::Integer(inode.device as i64),  
-         Value::Integer(inode.number as i64),
-    ];
-    match inode.btime {
-        Some(btime) => sql_args.push(Value::Integer(btime as i64)),
-                    None => sql_args.push(Value::Null),
+fn merge_tags_into(connection: &Connection, sources: &[&str], dst: &str) -> Result<()> {
+    let mut source_ids = Vec::new();
+    for source in sources {
+        source_ids.push(tag_id(connection, source)?);
     }
-    cursor.bind(&sql_args).unwrap();
-    let mut inode_id = 0;
-    while let Some(row) = cursor.next().unwrap() {
-        inode_id = row[0].as_integer().unwrap();
+    {
+        let mut cursor = connection
+            .prepare("INSERT OR IGNORE INTO tags(name) VALUES(?)")?
+            .cursor();
+        cursor.bind(&[Value::String(dst.to_string())])?;
+        while cursor.next()?.is_some() {}
     }
+    let dst_id = tag_id(connection, dst)?;
+    for source_id in source_ids {
+        if source_id == dst_id { continue }
+        // UNIQUE(tag_id, inode_id) drops relations dst already has.
+        connection.execute(format!("
+            INSERT OR IGNORE INTO relation_tag_inode(tag_id, inode_id)
+            SELECT {dst_id}, inode_id FROM relation_tag_inode WHERE tag_id = {source_id};
+            INSERT OR IGNORE INTO relation_tag_file(tag_id, file_id)
+            SELECT {dst_id}, file_id FROM relation_tag_file WHERE tag_id = {source_id};
+            DELETE FROM tags WHERE id = {source_id};
+            "))?;
+    }
+    Ok(())
+}
 
-    let sql_str = 
-    "SELECT DISTINCT b.name FROM relation_tag_inode a 
-    LEFT JOIN tags b ON b.id = a.tag_id
-    WHERE a.inode_id = ?";
+fn tag_id(connection: &Connection, tag_name: &str) -> Result<i64> {
     let mut cursor = connection
-        .prepare(&sql_str)
-        .unwrap()
+        .prepare("SELECT id FROM tags WHERE name = ?")?
         .cursor();
-    cursor.bind(&[Value::Integer(inode_id)]).unwrap();
-    let mut tag_names = Vec::new();
-    while let Some(row) = cursor.next().unwrap() {
-        tag_names.push(row[0].as_string().unwrap().to_owned());
+    cursor.bind(&[Value::String(tag_name.to_string())])?;
+    match cursor.next()? {
+        Some(row) => Ok(row[0].as_integer().unwrap_or(0)),
+        None => Err(Error::NotFound(format!("tag {tag_name:?}"))),
+    }
+}
+
+// Deletes the tags in tag_names that are no longer attached to any inode or file.
+pub(crate) fn prune_tags(connection: &Connection, tag_names: &[&str]) -> Result<()> {
+    let sql_str = format!(
+        "
+        DELETE FROM tags WHERE name IN ({}) 
+        AND id NOT IN (SELECT tag_id FROM relation_tag_inode) 
+        AND id NOT IN (SELECT tag_id FROM relation_tag_file);
+        ", 
+        vec!["?"; tag_names.len()].join(","),
+    );
+    let sql_args: Vec<Value> = tag_names.iter().map(|&val| {
+        Value::String(val.to_string())
+    }).collect();
+    let mut cursor = connection
+        .prepare(&sql_str)?
+        .cursor();
+    cursor.bind(&sql_args)?;
+    while cursor.next()?.is_some() {}
+    Ok(())
+}
+
+pub fn list_tags(connection: &Connection) -> Result<Vec<String>> {
+    list_tags_iter(connection)?.collect()
+}
+
+/// Like [`list_tags`], reading the names as the iterator advances.
+pub fn list_tags_iter(connection: &Connection) -> Result<Iter<'_, String>> {
+    let sql_str = "SELECT DISTINCT name FROM tags ORDER BY name";
+    Ok(Iter::rows(sql::Rows::new(connection, sql_str, &[])?, read_name))
+}
+
+/// Every tag with the number of inodes using it, most used first.
+pub fn list_tag_counts(connection: &Connection) -> Result<Vec<Tag>> {
+    list_tag_counts_iter(connection)?.collect()
+}
+
+/// Like [`list_tag_counts`], reading the tags as the iterator advances.
+pub fn list_tag_counts_iter(connection: &Connection) -> Result<Iter<'_, Tag>> {
+    let sql_str = "SELECT name, inode_num FROM tags ORDER BY inode_num DESC, name";
+    Ok(Iter::rows(sql::Rows::new(connection, sql_str, &[])?, |row| Tag {
+        name: row[0].as_string().unwrap_or_default().to_owned(),
+        inode_num: row[1].as_integer().unwrap_or(0) as u64,
+    }))
+}
+
+/// What the database knows about the tags of a file's inode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InodeTags<T = Vec<String>> {
+    /// The inode was never tagged, or was dropped by [`gc`].
+    Unknown,
+    /// The inode number was tagged when it belonged to another file: the
+    /// stored btime, given here, differs from the file's. Its tags are not
+    /// this file's; [`gc`] drops such entries.
+    Stale { btime: Option<u64> },
+    /// The tags of the inode, possibly none left.
+    Tagged(T),
+}
+
+impl<T> InodeTags<T> {
+    /// The tags if known, none otherwise.
+    pub fn tags(self) -> Option<T> {
+        match self {
+            InodeTags::Tagged(tags) => Some(tags),
+            _ => None,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> InodeTags<U> {
+        match self {
+            InodeTags::Unknown => InodeTags::Unknown,
+            InodeTags::Stale { btime } => InodeTags::Stale { btime },
+            InodeTags::Tagged(tags) => InodeTags::Tagged(f(tags)),
+        }
+    }
+}
+
+impl InodeTags<Iter<'_, String>> {
+    /// Reads all the tags.
+    pub fn collect(self) -> Result<InodeTags> {
+        match self {
+            InodeTags::Tagged(tags) => Ok(InodeTags::Tagged(tags.collect::<Result<_>>()?)),
+            state => Ok(state.map(|_| Vec::new())),
+        }
+    }
+}
+
+/// Looks up the tags of `inode`. A btime unknown on either side is taken to
+/// match, see [`INode::same_file`].
+pub fn get_tags(connection: &Connection, inode: INode) -> Result<InodeTags> {
+    get_tags_iter(connection, &inode)?.collect()
+}
+
+/// Like [`get_tags`], reading the names as the iterator advances.
+pub fn get_tags_iter<'l>(connection: &'l Connection, inode: &INode) -> Result<InodeTags<Iter<'l, String>>> {
+    let sql_str = "SELECT id, CAST(strftime('%s', btime) AS INT) FROM inodes WHERE device = ? AND number = ?";
+    let sql_args = [Value::Integer(sql::to_int(inode.device)), Value::Integer(sql::to_int(inode.number))];
+    let Some(row) = sql::Rows::new(connection, sql_str, &sql_args)?.next()? else {
+        return Ok(InodeTags::Unknown)
+    };
+    let stored = INode { btime: row[1].as_integer().map(|v| v as u64), ..inode.clone() };
+    if !stored.same_file(inode) {
+        return Ok(InodeTags::Stale { btime: stored.btime })
+    }
+    let sql_str = 
+    "SELECT b.name FROM relation_tag_inode a 
+    JOIN tags b ON b.id = a.tag_id
+    WHERE a.inode_id = ? ORDER BY a.tag_id";
+    let sql_args = [row[0].clone()];
+    Ok(InodeTags::Tagged(Iter::rows(sql::Rows::new(connection, sql_str, &sql_args)?, read_name)))
+}
+
+// The tags table rejects empty names with a CHECK constraint, and the CLI uses
+// ',' to separate tags, so catch both here with a useful error.
+pub(crate) fn check_tag_names(tag_names: &[&str]) -> Result<()> {
+    for tag_name in tag_names {
+        if tag_name.is_empty() || tag_name.contains(',') {
+            return Err(Error::InvalidTag(tag_name.to_string()));
+        }
     }
-    return tag_names;
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,251 @@
+//! A [`Storage`] kept in memory, for tests and for short-lived tools that
+//! need no database file. It has no content, path index or gc support.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::{check_tag_names, Error, INode, InodeTags, Iter, Query, Result, Stats, Storage, Tag};
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    data: RefCell<Data>,
+    // The data as of each open transaction's begin, innermost last.
+    saved: RefCell<Vec<Data>>,
+}
+
+// Ids are handed out like SQLite's INTEGER PRIMARY KEY, one past the largest,
+// so results come in the same order as from the database.
+#[derive(Debug, Default, Clone)]
+struct Data {
+    tags: BTreeMap<i64, String>,
+    tag_ids: HashMap<String, i64>,
+    inodes: BTreeMap<i64, INode>,
+    inode_ids: HashMap<(u64, u64), i64>,
+    // (tag id, inode id)
+    relations: BTreeSet<(i64, i64)>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl Data {
+    fn tag_id(&self, tag_name: &str) -> Result<i64> {
+        self.tag_ids.get(tag_name).copied().ok_or_else(|| Error::NotFound(format!("tag {tag_name:?}")))
+    }
+
+    fn insert_tag(&mut self, tag_name: &str) -> i64 {
+        if let Some(id) = self.tag_ids.get(tag_name) {
+            return *id
+        }
+        let id = self.tags.keys().next_back().map_or(1, |id| id + 1);
+        self.tags.insert(id, tag_name.to_string());
+        self.tag_ids.insert(tag_name.to_string(), id);
+        id
+    }
+
+    fn delete_tag(&mut self, id: i64) {
+        if let Some(name) = self.tags.remove(&id) {
+            self.tag_ids.remove(&name);
+        }
+        self.relations.retain(|(tag_id, _)| *tag_id != id);
+    }
+
+    // Like INSERT OR IGNORE then UPDATE of the btime when one is known. A
+    // different known btime drops the tags of the file the number was used by.
+    fn insert_inode(&mut self, inode: &INode) -> Option<i64> {
+        if inode.device == 0 || inode.number == 0 {
+            return None
+        }
+        let key = (inode.device, inode.number);
+        if let Some(&id) = self.inode_ids.get(&key) {
+            if inode.btime.is_some() {
+                if let Some(stored) = self.inodes.get_mut(&id) {
+                    let reused = stored.btime.is_some() && stored.btime != inode.btime;
+                    stored.btime = inode.btime;
+                    if reused {
+                        self.relations.retain(|(_, inode_id)| *inode_id != id);
+                    }
+                }
+            }
+            return Some(id)
+        }
+        let id = self.inodes.keys().next_back().map_or(1, |id| id + 1);
+        self.inodes.insert(id, inode.clone());
+        self.inode_ids.insert(key, id);
+        Some(id)
+    }
+
+    // The tags of every inode, by inode id.
+    fn inode_tags(&self) -> HashMap<i64, HashSet<&str>> {
+        let mut inode_tags: HashMap<i64, HashSet<&str>> = HashMap::new();
+        for (tag_id, inode_id) in &self.relations {
+            inode_tags.entry(*inode_id).or_default().insert(&self.tags[tag_id]);
+        }
+        inode_tags
+    }
+
+    fn inode_num(&self, tag_id: i64) -> u64 {
+        self.relations.range((tag_id, i64::MIN)..=(tag_id, i64::MAX)).count() as u64
+    }
+}
+
+fn iter<'l, T: 'l>(items: Vec<T>) -> Iter<'l, T> {
+    Iter::new(items.into_iter().map(Ok))
+}
+
+impl Storage for MemoryStorage {
+    fn begin(&self) -> Result<()> {
+        self.saved.borrow_mut().push(self.data.borrow().clone());
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.saved.borrow_mut().pop();
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        if let Some(data) = self.saved.borrow_mut().pop() {
+            *self.data.borrow_mut() = data;
+        }
+        Ok(())
+    }
+
+    fn add(&self, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
+        if inodes.is_empty() || tag_names.is_empty() { return Ok(()) }
+        check_tag_names(tag_names)?;
+        let mut data = self.data.borrow_mut();
+        let tag_ids: Vec<i64> = tag_names.iter().map(|tag_name| data.insert_tag(tag_name)).collect();
+        for inode in inodes {
+            let Some(inode_id) = data.insert_inode(inode) else { continue };
+            for tag_id in &tag_ids {
+                data.relations.insert((*tag_id, inode_id));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, inodes: &[INode], tag_names: &[&str], prune: bool) -> Result<()> {
+        if inodes.is_empty() || tag_names.is_empty() { return Ok(()) }
+        let mut data = self.data.borrow_mut();
+        let tag_ids: Vec<i64> = tag_names.iter().filter_map(|tag_name| data.tag_id(tag_name).ok()).collect();
+        let inode_ids: Vec<i64> = inodes.iter()
+            .filter_map(|inode| data.inode_ids.get(&(inode.device, inode.number)).copied())
+            .collect();
+        for tag_id in &tag_ids {
+            for inode_id in &inode_ids {
+                data.relations.remove(&(*tag_id, *inode_id));
+            }
+        }
+        if prune {
+            for tag_id in tag_ids {
+                if data.inode_num(tag_id) == 0 {
+                    data.delete_tag(tag_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_tag(&self, old: &str, new: &str) -> Result<()> {
+        check_tag_names(&[new])?;
+        let mut data = self.data.borrow_mut();
+        let id = data.tag_id(old)?;
+        if old == new { return Ok(()) }
+        if data.tag_ids.contains_key(new) {
+            return Err(Error::TagExists(new.to_string()));
+        }
+        data.tag_ids.remove(old);
+        data.tag_ids.insert(new.to_string(), id);
+        data.tags.insert(id, new.to_string());
+        Ok(())
+    }
+
+    fn merge_tags(&self, sources: &[&str], dst: &str) -> Result<()> {
+        check_tag_names(&[dst])?;
+        let mut data = self.data.borrow_mut();
+        let source_ids = sources.iter().map(|source| data.tag_id(source)).collect::<Result<Vec<i64>>>()?;
+        let dst_id = data.insert_tag(dst);
+        for source_id in source_ids {
+            if source_id == dst_id { continue }
+            let moved: Vec<(i64, i64)> = data.relations.iter()
+                .filter(|(tag_id, _)| *tag_id == source_id)
+                .map(|(_, inode_id)| (dst_id, *inode_id))
+                .collect();
+            data.relations.extend(moved);
+            data.delete_tag(source_id);
+        }
+        Ok(())
+    }
+
+    fn get_inodes(&self, tag_names: &[&str]) -> Result<Iter<'_, INode>> {
+        let tag_names: HashSet<&str> = tag_names.iter().copied().collect();
+        let data = self.data.borrow();
+        let inode_tags = data.inode_tags();
+        let inodes = data.inodes.iter()
+            .filter(|(id, _)| inode_tags.get(id).is_some_and(|tags| {
+                !tag_names.is_empty() && tag_names.iter().all(|tag_name| tags.contains(tag_name))
+            }))
+            .map(|(_, inode)| inode.clone())
+            .collect();
+        Ok(iter(inodes))
+    }
+
+    fn search(&self, query: &Query) -> Result<Iter<'_, INode>> {
+        let data = self.data.borrow();
+        let inode_tags = data.inode_tags();
+        let none = HashSet::new();
+        let inodes = data.inodes.iter()
+            .filter(|(id, _)| {
+                let tags = inode_tags.get(id).unwrap_or(&none);
+                query.matches(&|tag_name| tags.contains(tag_name))
+            })
+            .map(|(_, inode)| inode.clone())
+            .collect();
+        Ok(iter(inodes))
+    }
+
+    fn get_tags(&self, inode: &INode) -> Result<InodeTags<Iter<'_, String>>> {
+        let data = self.data.borrow();
+        let Some(id) = data.inode_ids.get(&(inode.device, inode.number)) else {
+            return Ok(InodeTags::Unknown)
+        };
+        let stored = &data.inodes[id];
+        if !stored.same_file(inode) {
+            return Ok(InodeTags::Stale { btime: stored.btime })
+        }
+        let tag_names = data.relations.iter()
+            .filter(|(_, inode_id)| inode_id == id)
+            .map(|(tag_id, _)| data.tags[tag_id].clone())
+            .collect::<Vec<String>>();
+        Ok(InodeTags::Tagged(iter(tag_names)))
+    }
+
+    fn list_tags(&self) -> Result<Iter<'_, String>> {
+        let data = self.data.borrow();
+        let mut tag_names: Vec<String> = data.tags.values().cloned().collect();
+        tag_names.sort();
+        Ok(iter(tag_names))
+    }
+
+    fn list_tag_counts(&self) -> Result<Iter<'_, Tag>> {
+        let data = self.data.borrow();
+        let mut tags: Vec<Tag> = data.tags.iter().map(|(id, name)| {
+            Tag { name: name.clone(), inode_num: data.inode_num(*id) }
+        }).collect();
+        tags.sort_by(|a, b| b.inode_num.cmp(&a.inode_num).then_with(|| a.name.cmp(&b.name)));
+        Ok(iter(tags))
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let data = self.data.borrow();
+        Ok(Stats {
+            tags: data.tags.len() as u64,
+            inodes: data.inodes.len() as u64,
+            relations: data.relations.len() as u64,
+            ..Stats::default()
+        })
+    }
+}
@@ -1,24 +1,95 @@
 use std::collections::{HashMap, BTreeMap};
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 
-pub enum OptArg<'a> { None, Mandatory(&'a str) }
-pub type OptCheck<'a> = BTreeMap<u8, (OptArg<'a>, &'a str)>;
+/// The argument of an option, with its default. A `Mandatory` default is used
+/// when the option is not given at all; an `Optional` one when the option is
+/// given without an argument. An `Optional` argument can only be given as
+/// `--long=value`: the short option is a plain flag meaning the default, so
+/// that it still clusters with others (`-au`). An empty default means none.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OptArg<'a> {
+    #[default]
+    None,
+    Mandatory(&'a str),
+    Optional(&'a str),
+}
+
+/// What an option argument or an operand completes to, see [`completion`](crate::completion).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Complete<'a> {
+    #[default]
+    Nothing,
+    Files,
+    Dirs,
+    Words(&'a [&'a str]),
+    /// A tag name.
+    Tags,
+    /// Comma separated tag names.
+    TagList,
+}
+
+/// One option of an [`OptCheck`], keyed there by its short name.
+#[derive(Debug, Default, Clone)]
+pub struct Opt<'a> {
+    /// Long name, given as `--long` or `--long=value`; empty for none.
+    pub long: &'a str,
+    pub arg: OptArg<'a>,
+    /// Name of the argument in the help text, e.g. `<db>`.
+    pub value: &'a str,
+    /// What the argument completes to.
+    pub complete: Complete<'a>,
+    /// Subcommands the option applies to, empty for all.
+    pub commands: &'a [&'a str],
+    pub help: &'a str,
+}
+
+pub type OptCheck<'a> = BTreeMap<u8, Opt<'a>>;
+pub type OptList<'a> = HashMap<u8, Vec<&'a str>>;
 
-pub fn get_opt_per<'a>(args: &'a [String], opt_check: &OptCheck<'a>) -> 
+impl Opt<'_> {
+    fn applies(&self, command: &str) -> bool {
+        self.commands.is_empty() || self.commands.contains(&command)
+    }
+}
+
+pub fn get_opt_per<'a>(args: &'a [String], opt_check: &OptCheck<'a>) ->
 Result<(HashMap<u8, &'a str>, Vec<&'a str>), Error> {
-    let (mut options, mut operands): (HashMap<u8, &str>, Vec<&str>) = 
-    (HashMap::new(), Vec::new());
+    let (options, operands) = get_opt_list(args, opt_check)?;
+    let options = options.into_iter().filter_map(|(k, v)| Some((k, *v.last()?))).collect();
+    Ok((options, operands))
+}
 
-    for (k, v) in opt_check {
-        match v.0 {
-            OptArg::Mandatory(default_arg) => {
-                if default_arg != "" {
-                    options.insert(*k, default_arg);
-                }
-            }, 
-            OptArg::None => (),
+/// Like get_opt_per, but keeps every argument of an option given more than
+/// once (e.g. `-d a -d b`), in order. Defaults apply only to options not given.
+pub fn get_opt_list<'a>(args: &'a [String], opt_check: &OptCheck<'a>) ->
+Result<(OptList<'a>, Vec<&'a str>), Error> {
+    let (mut options, operands) = parse(args, opt_check)?;
+    defaults(&mut options, opt_check, None);
+    Ok((options, operands))
+}
+
+/// Like get_opt_list, but the first operand names a subcommand: options that
+/// do not apply to it are rejected, and only its own get defaults.
+pub fn get_opt_command<'a>(args: &'a [String], opt_check: &OptCheck<'a>) ->
+Result<(OptList<'a>, Vec<&'a str>), Error> {
+    let (mut options, operands) = parse(args, opt_check)?;
+    let command = operands.first().copied();
+    if let Some(command) = command {
+        for (k, opt) in opt_check {
+            if options.contains_key(k) && !opt.applies(command) {
+                return Err(Error::other(
+                    format!("option does not apply to {command} -- '{}'", *k as char)));
+            }
         }
     }
+    defaults(&mut options, opt_check, command);
+    Ok((options, operands))
+}
+
+fn parse<'a>(args: &'a [String], opt_check: &OptCheck<'a>) ->
+Result<(OptList<'a>, Vec<&'a str>), Error> {
+    let (mut options, mut operands): (OptList, Vec<&str>) =
+    (HashMap::new(), Vec::new());
 
     let mut i = 0;
     while i < args.len() {
@@ -29,32 +100,62 @@ Result<(HashMap<u8, &'a str>, Vec<&'a str>), Error> {
             }
             break
         }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            let Some((s_opt, check)) = opt_check.iter().find(|(_, opt)| !opt.long.is_empty() && opt.long == name) else {
+                return Err(Error::other(format!("unrecognized option '--{name}'")));
+            };
+            let value = match (check.arg, value) {
+                (OptArg::None, None) => "",
+                (OptArg::None, Some(_)) => return Err(Error::other(
+                    format!("option '--{name}' doesn't allow an argument"))),
+                (_, Some(value)) => value,
+                (OptArg::Optional(default_arg), None) => default_arg,
+                (OptArg::Mandatory(_), None) => {
+                    if i+2 > args.len() {
+                        return Err(Error::other(
+                            format!("option '--{name}' requires an argument")));
+                    }
+                    i += 1;
+                    args[i].as_str()
+                },
+            };
+            options.entry(*s_opt).or_default().push(value);
+            i += 1;
+            continue
+        }
         let arg_b = args[i].as_bytes();
         if arg_b.len() >= 2 && arg_b[0] == b'-' {
             let arg_b = &arg_b[1..arg_b.len()];
             for (ii, s_opt) in arg_b.iter().enumerate() {
                 match opt_check.get(s_opt) {
                     Some(check) => {
-                        match &check.0 {
+                        match &check.arg {
                             OptArg::None => {
-                                options.insert(*s_opt, "");
+                                options.entry(*s_opt).or_default().push("");
+                            },
+                            OptArg::Optional(default_arg) => {
+                                options.entry(*s_opt).or_default().push(default_arg);
                             },
                             OptArg::Mandatory(_) => {
                                 if ii+2 > arg_b.len() {
                                     if i+2 > args.len() {
-                                        return Err(Error::new(ErrorKind::Other,
+                                        return Err(Error::other(
                                             format!("option requires an argument -- '{}'", *s_opt as char)));
-                                    } 
-                                    options.insert(*s_opt, args[i+1].as_str());
+                                    }
+                                    options.entry(*s_opt).or_default().push(args[i+1].as_str());
                                     i += 1;
                                 } else {
-                                    options.insert(*s_opt, &args[i][(ii+2)..(1+arg_b.len())]);
+                                    options.entry(*s_opt).or_default().push(&args[i][(ii+2)..(1+arg_b.len())]);
                                     break;
                                 }
                             },
                         }
                     },
-                    None => return Err(Error::new(ErrorKind::Other, 
+                    None => return Err(Error::other(
                         format!("invalid option -- '{}'", *s_opt as char))),
                 }
             }
@@ -66,13 +167,55 @@ Result<(HashMap<u8, &'a str>, Vec<&'a str>), Error> {
     Ok((options, operands))
 }
 
-pub fn usage(opt_check: &OptCheck) -> String {
+fn defaults<'a>(options: &mut OptList<'a>, opt_check: &OptCheck<'a>, command: Option<&str>) {
+    for (k, v) in opt_check {
+        if command.is_some_and(|command| !v.applies(command)) {
+            continue
+        }
+        match v.arg {
+            OptArg::Mandatory(default_arg) => {
+                if !default_arg.is_empty() {
+                    options.entry(*k).or_insert_with(|| vec![default_arg]);
+                }
+            },
+            OptArg::None | OptArg::Optional(_) => (),
+        }
+    }
+}
+
+/// Help text of the options, one per line. With a subcommand, only the
+/// options that apply to it; otherwise all of them, each marked with the
+/// subcommands it is limited to.
+pub fn usage(opt_check: &OptCheck, command: Option<&str>) -> String {
+    let mut names = Vec::new();
+    for (k, v) in opt_check {
+        if command.is_some_and(|command| !v.applies(command)) {
+            continue
+        }
+        let mut name = format!("-{}", *k as char);
+        if !v.long.is_empty() {
+            name.push_str(&format!(", --{}", v.long));
+        }
+        match v.arg {
+            OptArg::None => (),
+            OptArg::Mandatory(_) => name.push_str(&format!(" {}", v.value)),
+            OptArg::Optional(_) => name.push_str(&format!("[={}]", v.value)),
+        }
+        names.push((name, v));
+    }
+
     let mut ret = Vec::new();
-    if !opt_check.is_empty() {
+    if !names.is_empty() {
         ret.push(String::from("OPTIONS: "));
     }
-    for (_, v) in opt_check {
-        ret.push(format!("\t{}", v.1));
+    let width = names.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, v) in names {
+        let commands = if command.is_none() && !v.commands.is_empty() {
+            format!("[{}]", v.commands.join(","))
+        } else {
+            String::new()
+        };
+        ret.push(format!("\t{name:width$}  {commands}{}", v.help));
     }
     ret.join("\n")
-}
\ No newline at end of file
+}
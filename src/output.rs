@@ -0,0 +1,166 @@
+//! Output formats shared by the CLI subcommands.
+//!
+//! Every subcommand writes records, lists of named fields. The field names
+//! are stable: `path`, `device`, `inode`, `btime`, `tags`, plus `tag`,
+//! `count`, `md5`, `sha256` and `status` where they apply.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The fields shown by default, tab-separated, one record per line.
+    Plain,
+    /// Like Plain, but every record ends with NUL instead of newline.
+    Nul,
+    /// A JSON array of objects.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+    /// A header line and one line per record.
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Format> {
+        match s {
+            "plain" => Ok(Format::Plain),
+            "nul" => Ok(Format::Nul),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            _ => Err(io::Error::other(format!("unknown output format '{s}'"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Str(String),
+    Int(u64),
+    List(Vec<String>),
+    Null,
+}
+
+impl From<&str> for Field {
+    fn from(s: &str) -> Field { Field::Str(s.to_string()) }
+}
+
+impl From<String> for Field {
+    fn from(s: String) -> Field { Field::Str(s) }
+}
+
+impl From<u64> for Field {
+    fn from(n: u64) -> Field { Field::Int(n) }
+}
+
+impl From<Option<u64>> for Field {
+    fn from(n: Option<u64>) -> Field { n.map_or(Field::Null, Field::Int) }
+}
+
+impl From<Vec<String>> for Field {
+    fn from(list: Vec<String>) -> Field { Field::List(list) }
+}
+
+pub type Record<'a> = [(&'a str, Field)];
+
+pub struct Writer<W: Write> {
+    out: W,
+    format: Format,
+    plain: Vec<String>,
+    count: usize,
+}
+
+impl<W: Write> Writer<W> {
+    /// `plain` names the fields printed in the Plain and Nul formats; the
+    /// other formats print every field.
+    pub fn new(out: W, format: Format, plain: &[&str]) -> Writer<W> {
+        Writer { out, format, plain: plain.iter().map(|s| s.to_string()).collect(), count: 0 }
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self.format {
+            Format::Plain | Format::Nul => {
+                let fields: Vec<String> = self.plain.iter().map(|name| {
+                    record.iter().find(|(k, _)| k == name).map_or(String::new(), |(_, v)| plain(v))
+                }).collect();
+                let end = if self.format == Format::Nul { '\0' } else { '\n' };
+                write!(self.out, "{}{end}", fields.join("\t"))?;
+            },
+            Format::Json => {
+                write!(self.out, "{}\n  {}", if self.count == 0 { "[" } else { "," }, json(record))?;
+            },
+            Format::JsonLines => writeln!(self.out, "{}", json(record))?,
+            Format::Csv => {
+                if self.count == 0 {
+                    let header: Vec<String> = record.iter().map(|(k, _)| csv(k)).collect();
+                    writeln!(self.out, "{}", header.join(","))?;
+                }
+                let row: Vec<String> = record.iter().map(|(_, v)| csv(&plain(v))).collect();
+                writeln!(self.out, "{}", row.join(","))?;
+            },
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Closes the JSON array and flushes.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.format == Format::Json {
+            writeln!(self.out, "{}", if self.count == 0 { "[]" } else { "\n]" })?;
+        }
+        self.out.flush()
+    }
+}
+
+fn plain(field: &Field) -> String {
+    match field {
+        Field::Str(s) => s.clone(),
+        Field::Int(n) => n.to_string(),
+        Field::List(list) => list.join(","),
+        Field::Null => String::new(),
+    }
+}
+
+fn json(record: &Record) -> String {
+    let fields: Vec<String> = record.iter().map(|(k, v)| {
+        let v = match v {
+            Field::Str(s) => json_str(s),
+            Field::Int(n) => n.to_string(),
+            Field::List(list) => {
+                let list: Vec<String> = list.iter().map(|s| json_str(s)).collect();
+                format!("[{}]", list.join(","))
+            },
+            Field::Null => String::from("null"),
+        };
+        format!("{}:{}", json_str(k), v)
+    }).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
@@ -0,0 +1,213 @@
+use sqlite3::Value;
+use crate::{Error, Result};
+
+/// A boolean expression over tag names, e.g. `photo and (2023 or 2024) and not private`.
+///
+/// Grammar, loosest binding first:
+///
+/// ```text
+/// or   := and ("or" and)*
+/// and  := not (("and" | ",") not)*
+/// not  := "not" not | "(" or ")" | tag
+/// tag  := bare word | "double quoted"
+/// ```
+///
+/// Keywords are case-insensitive; quote a tag to use a keyword as a tag name.
+/// A query without parentheses or quotes that has commas or no keywords is
+/// the tag list form `tag1[,tag2...]` of the CLI instead: the tags are split
+/// on commas only, so they may contain spaces or be keywords (`my photos`,
+/// `my photos,not`), and all must match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Tag(String),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    LParen,
+    RParen,
+    Comma,
+}
+
+pub fn parse(input: &str) -> Result<Query> {
+    let keywords = input.split_whitespace().any(is_keyword);
+    if !input.contains(['(', ')', '"']) && (input.contains(',') || !keywords) {
+        return tag_list(input)
+    }
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens: &tokens, pos: 0 };
+    let query = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(query),
+        Some(token) => Err(invalid(input, &format!("unexpected {token:?}"))),
+    }
+}
+
+impl Query {
+    /// Writes a condition on `id` matching this expression, where `relation`
+    /// is the table linking tags to `column`, e.g. relation_tag_inode.inode_id.
+    pub(crate) fn to_sql(&self, relation: &str, column: &str, sql: &mut String, args: &mut Vec<Value>) {
+        match self {
+            Query::Tag(name) => {
+                sql.push_str(&format!("id IN (SELECT a.{column} FROM {relation} a \
+                    JOIN tags c ON a.tag_id = c.id WHERE c.name = ?)"));
+                args.push(Value::String(name.clone()));
+            },
+            Query::And(a, b) | Query::Or(a, b) => {
+                let op = if matches!(self, Query::And(..)) { " AND " } else { " OR " };
+                sql.push('(');
+                a.to_sql(relation, column, sql, args);
+                sql.push_str(op);
+                b.to_sql(relation, column, sql, args);
+                sql.push(')');
+            },
+            Query::Not(a) => {
+                sql.push_str("NOT ");
+                a.to_sql(relation, column, sql, args);
+            },
+        }
+    }
+
+    /// Whether something carrying the tags for which `has` is true matches.
+    pub fn matches(&self, has: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Query::Tag(name) => has(name),
+            Query::And(a, b) => a.matches(has) && b.matches(has),
+            Query::Or(a, b) => a.matches(has) || b.matches(has),
+            Query::Not(a) => !a.matches(has),
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Query> {
+        let mut query = self.and()?;
+        while self.keyword("or") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query> {
+        let mut query = self.not()?;
+        while self.keyword("and") || self.token(&Token::Comma) {
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query> {
+        if self.keyword("not") {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        if self.token(&Token::LParen) {
+            let query = self.or()?;
+            if !self.token(&Token::RParen) {
+                return Err(Error::InvalidQuery(String::from("missing ')'")));
+            }
+            return Ok(query);
+        }
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(word)) if !is_keyword(word) => {
+                self.pos += 1;
+                Ok(Query::Tag(word.clone()))
+            },
+            Some(Token::Quoted(word)) => {
+                self.pos += 1;
+                Ok(Query::Tag(word.clone()))
+            },
+            Some(token) => Err(Error::InvalidQuery(format!("expect tag, found {token:?}"))),
+            None => Err(Error::InvalidQuery(String::from("expect tag, found end of query"))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn token(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The tag names of a list `tag1[,tag2...]`, without the spaces around
+/// them. Tagging and searching both read lists through here, so a name is
+/// found as it was stored.
+pub fn split_tags(list: &str) -> Vec<&str> {
+    list.split(',').map(str::trim).collect()
+}
+
+fn tag_list(input: &str) -> Result<Query> {
+    let mut tags = split_tags(input).into_iter().map(|name| match name {
+        "" => Err(invalid(input, "empty tag")),
+        name => Ok(Query::Tag(name.to_string())),
+    });
+    let first = tags.next().unwrap_or_else(|| Err(invalid(input, "empty tag")))?;
+    tags.try_fold(first, |query, tag| Ok(Query::And(Box::new(query), Box::new(tag?))))
+}
+
+fn is_keyword(word: &str) -> bool {
+    ["and", "or", "not"].iter().any(|k| word.eq_ignore_ascii_case(k))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); },
+            '(' => { chars.next(); tokens.push(Token::LParen) },
+            ')' => { chars.next(); tokens.push(Token::RParen) },
+            ',' => { chars.next(); tokens.push(Token::Comma) },
+            '"' => {
+                chars.next();
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid(input, "unterminated quote")),
+                    }
+                }
+                tokens.push(Token::Quoted(word));
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "(),\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+fn invalid(input: &str, msg: &str) -> Error {
+    Error::InvalidQuery(format!("{msg} in {input:?}"))
+}
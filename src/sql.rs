@@ -1,87 +1,328 @@
-use sqlite3::Connection;
-
-pub fn init(db_file: &str) ->  Connection {
-    let connection = sqlite3::open(db_file).unwrap();
-    connection
-        .execute(
-            "
-            CREATE TABLE IF NOT EXISTS tags (
-                `id` INTEGER PRIMARY KEY, 
-                `name` TEXT NOT NULL, 
-                `inode_num` INTEGER NOT NULL DEFAULT 0, 
-                `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(name),
-                CHECK(name <> '')
-            );
-            ",
-            // INSERT INTO tags (name) VALUES ('Alis');
-            // INSERT INTO tags (name) VALUES ('');
-            // INSERT INTO tags (name, num) VALUES ('Bob', 69);
-        )
-        .unwrap();
-    connection
-        .execute(
-            "
-            CREATE TABLE IF NOT EXISTS inodes (
-                `id` INTEGER PRIMARY KEY, 
-                `device` INTEGER NOT NULL,
-                `number` INTEGER NOT NULL, 
-                `btime` TIMESTAMP DEFAULT NULL,
-                `tag_num` INTEGER NOT NULL DEFAULT 0, 
-                `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(device, number),
-                CHECK(device <> 0 AND number <> 0)
-            );
-            ",
-            //INSERT INTO inodes (device, number) VALUES (123432, 89234);
-        )
-        .unwrap();
-    connection
-        .execute(
-            "
-            CREATE TABLE IF NOT EXISTS relation_tag_inode (
-                `id` INTEGER PRIMARY KEY, 
-                `tag_id` INTEGER NOT NULL,
-                `inode_id` INTEGER NOT NULL, 
-                `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(tag_id, inode_id), 
-                CHECK(tag_id <> 0 AND inode_id <> 0)
-            );
-            ",
-            //INSERT INTO relation_tag_inode (tag_id, inode_id) VALUES (123432, 89234);
-        )
-        .unwrap();
-
-
-    connection
-        .execute(
-            "
-            CREATE TABLE IF NOT EXISTS file (
-                `id` INTEGER PRIMARY KEY, 
-                `md5` TEXT NOT NULL UNIQUE,
-                `sha256` TEXT NOT NULL UNIQUE,
-                `path` TEXT,
-                `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            );
-            ",
-            //INSERT INTO relation_tag_inode (tag_id, inode_id) VALUES (123432, 89234);
-        )
-        .unwrap();
-    connection
-        .execute(
-            "
-            CREATE TABLE IF NOT EXISTS relation_tag_file (
-                `id` INTEGER PRIMARY KEY, 
-                `tag_id` INTEGER NOT NULL,
-                `file_id` INTEGER NOT NULL, 
-                `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                UNIQUE(tag_id, file_id), 
-                CHECK(tag_id <> 0 AND file_id <> 0)
-            );
-            ",
-            //INSERT INTO relation_tag_inode (tag_id, inode_id) VALUES (123432, 89234);
-        )
-        .unwrap();
-	
-	connection
-}
\ No newline at end of file
+use sqlite3::{Connection, State, Statement, Value};
+use crate::{Error, Result};
+
+// MIGRATIONS[i] upgrades a database from schema version i to i + 1; the
+// version is kept in PRAGMA user_version. Databases created before versioning
+// are at version 0 but already have the tables of version 1, hence the
+// IF NOT EXISTS there. Append new steps, never edit released ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: tags, inodes, content hashes and their relations
+    "
+        CREATE TABLE IF NOT EXISTS tags (
+            `id` INTEGER PRIMARY KEY, 
+            `name` TEXT NOT NULL, 
+            `inode_num` INTEGER NOT NULL DEFAULT 0, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(name),
+            CHECK(name <> '')
+        );
+        CREATE TABLE IF NOT EXISTS inodes (
+            `id` INTEGER PRIMARY KEY, 
+            `device` INTEGER NOT NULL,
+            `number` INTEGER NOT NULL, 
+            `btime` TIMESTAMP DEFAULT NULL,
+            `tag_num` INTEGER NOT NULL DEFAULT 0, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(device, number),
+            CHECK(device <> 0 AND number <> 0)
+        );
+        CREATE TABLE IF NOT EXISTS relation_tag_inode (
+            `id` INTEGER PRIMARY KEY, 
+            `tag_id` INTEGER NOT NULL,
+            `inode_id` INTEGER NOT NULL, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(tag_id, inode_id), 
+            CHECK(tag_id <> 0 AND inode_id <> 0)
+        );
+        CREATE TABLE IF NOT EXISTS file (
+            `id` INTEGER PRIMARY KEY, 
+            `md5` TEXT NOT NULL UNIQUE,
+            `sha256` TEXT NOT NULL UNIQUE,
+            `path` TEXT,
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS relation_tag_file (
+            `id` INTEGER PRIMARY KEY, 
+            `tag_id` INTEGER NOT NULL,
+            `file_id` INTEGER NOT NULL, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(tag_id, file_id), 
+            CHECK(tag_id <> 0 AND file_id <> 0)
+        );
+        ",
+    // 2: last known paths of tagged inodes
+    "
+        CREATE TABLE IF NOT EXISTS paths (
+            `id` INTEGER PRIMARY KEY, 
+            `inode_id` INTEGER NOT NULL,
+            `path` TEXT NOT NULL, 
+            `update_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(inode_id, path), 
+            CHECK(inode_id <> 0 AND path <> '')
+        );
+        ",
+    // 3: keep tags.inode_num and inodes.tag_num in step with relation_tag_inode
+    "
+        CREATE TRIGGER relation_tag_inode_insert AFTER INSERT ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num + 1 WHERE id = NEW.tag_id;
+            UPDATE inodes SET tag_num = tag_num + 1 WHERE id = NEW.inode_id;
+        END;
+        CREATE TRIGGER relation_tag_inode_delete AFTER DELETE ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num - 1 WHERE id = OLD.tag_id;
+            UPDATE inodes SET tag_num = tag_num - 1 WHERE id = OLD.inode_id;
+        END;
+        CREATE TRIGGER relation_tag_inode_update AFTER UPDATE OF tag_id, inode_id ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num - 1 WHERE id = OLD.tag_id;
+            UPDATE inodes SET tag_num = tag_num - 1 WHERE id = OLD.inode_id;
+            UPDATE tags SET inode_num = inode_num + 1 WHERE id = NEW.tag_id;
+            UPDATE inodes SET tag_num = tag_num + 1 WHERE id = NEW.inode_id;
+        END;
+        UPDATE tags SET inode_num = (SELECT COUNT(*) FROM relation_tag_inode WHERE tag_id = tags.id);
+        UPDATE inodes SET tag_num = (SELECT COUNT(*) FROM relation_tag_inode WHERE inode_id = inodes.id);
+        ",
+    // 4: foreign keys on the relation and path tables. SQLite cannot add them
+    // to existing tables, so rebuild those, dropping rows that already dangle.
+    "
+        CREATE TABLE relation_tag_inode_new (
+            `id` INTEGER PRIMARY KEY, 
+            `tag_id` INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            `inode_id` INTEGER NOT NULL REFERENCES inodes(id) ON DELETE CASCADE, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(tag_id, inode_id), 
+            CHECK(tag_id <> 0 AND inode_id <> 0)
+        );
+        INSERT INTO relation_tag_inode_new SELECT * FROM relation_tag_inode
+        WHERE tag_id IN (SELECT id FROM tags) AND inode_id IN (SELECT id FROM inodes);
+        DROP TABLE relation_tag_inode;
+        ALTER TABLE relation_tag_inode_new RENAME TO relation_tag_inode;
+
+        CREATE TABLE relation_tag_file_new (
+            `id` INTEGER PRIMARY KEY, 
+            `tag_id` INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            `file_id` INTEGER NOT NULL REFERENCES file(id) ON DELETE CASCADE, 
+            `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(tag_id, file_id), 
+            CHECK(tag_id <> 0 AND file_id <> 0)
+        );
+        INSERT INTO relation_tag_file_new SELECT * FROM relation_tag_file
+        WHERE tag_id IN (SELECT id FROM tags) AND file_id IN (SELECT id FROM file);
+        DROP TABLE relation_tag_file;
+        ALTER TABLE relation_tag_file_new RENAME TO relation_tag_file;
+
+        CREATE TABLE paths_new (
+            `id` INTEGER PRIMARY KEY, 
+            `inode_id` INTEGER NOT NULL REFERENCES inodes(id) ON DELETE CASCADE,
+            `path` TEXT NOT NULL, 
+            `update_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(inode_id, path), 
+            CHECK(inode_id <> 0 AND path <> '')
+        );
+        INSERT INTO paths_new SELECT * FROM paths WHERE inode_id IN (SELECT id FROM inodes);
+        DROP TABLE paths;
+        ALTER TABLE paths_new RENAME TO paths;
+
+        CREATE INDEX relation_tag_inode_inode_id ON relation_tag_inode(inode_id);
+        CREATE INDEX relation_tag_file_file_id ON relation_tag_file(file_id);
+
+        -- the counter triggers went with the old table
+        CREATE TRIGGER relation_tag_inode_insert AFTER INSERT ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num + 1 WHERE id = NEW.tag_id;
+            UPDATE inodes SET tag_num = tag_num + 1 WHERE id = NEW.inode_id;
+        END;
+        CREATE TRIGGER relation_tag_inode_delete AFTER DELETE ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num - 1 WHERE id = OLD.tag_id;
+            UPDATE inodes SET tag_num = tag_num - 1 WHERE id = OLD.inode_id;
+        END;
+        CREATE TRIGGER relation_tag_inode_update AFTER UPDATE OF tag_id, inode_id ON relation_tag_inode BEGIN
+            UPDATE tags SET inode_num = inode_num - 1 WHERE id = OLD.tag_id;
+            UPDATE inodes SET tag_num = tag_num - 1 WHERE id = OLD.inode_id;
+            UPDATE tags SET inode_num = inode_num + 1 WHERE id = NEW.tag_id;
+            UPDATE inodes SET tag_num = tag_num + 1 WHERE id = NEW.inode_id;
+        END;
+        UPDATE tags SET inode_num = (SELECT COUNT(*) FROM relation_tag_inode WHERE tag_id = tags.id);
+        UPDATE inodes SET tag_num = (SELECT COUNT(*) FROM relation_tag_inode WHERE inode_id = inodes.id);
+        ",
+    // 5: device and inode numbers above i64::MAX used to be written as SQL
+    // text, which SQLite read as REAL. Store them as integers like to_int
+    // does; precision the REAL lost is gone, and rows that do not fit even
+    // so (rounded up to 2^64) are dropped.
+    "
+        UPDATE OR IGNORE inodes SET device = CASE WHEN device >= 9223372036854775808.0
+            THEN CAST(device - 18446744073709551616.0 AS INTEGER) ELSE CAST(device AS INTEGER) END
+        WHERE typeof(device) = 'real';
+        UPDATE OR IGNORE inodes SET number = CASE WHEN number >= 9223372036854775808.0
+            THEN CAST(number - 18446744073709551616.0 AS INTEGER) ELSE CAST(number AS INTEGER) END
+        WHERE typeof(number) = 'real';
+        DELETE FROM inodes WHERE typeof(device) <> 'integer' OR typeof(number) <> 'integer';
+        -- foreign keys are off while migrating
+        DELETE FROM relation_tag_inode WHERE inode_id NOT IN (SELECT id FROM inodes);
+        DELETE FROM paths WHERE inode_id NOT IN (SELECT id FROM inodes);
+        ",
+];
+
+/// The schema version this build creates and understands.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// How long a statement waits for another connection's lock before failing.
+pub const BUSY_TIMEOUT_MS: usize = 5000;
+
+// How often retry runs an operation that keeps failing with a busy error.
+const BUSY_RETRIES: u32 = 5;
+
+/// Opens (or creates) the database and brings its schema up to date.
+/// Databases written by a newer version are refused.
+///
+/// The database is switched to WAL journaling, so readers and one writer
+/// do not block each other, and statements wait up to [`BUSY_TIMEOUT_MS`]
+/// for locks held by other processes.
+pub fn init(db_file: &str) -> Result<Connection> {
+    let mut connection = sqlite3::open(db_file)?;
+    connection.set_busy_timeout(BUSY_TIMEOUT_MS)?;
+    // Persistent, so only the first open of a database changes anything.
+    retry(|| Ok(connection.execute("PRAGMA journal_mode = WAL")?))?;
+    migrate(&connection)?;
+    check_schema(&connection)?;
+    // Off by default in SQLite and per connection. Must be set outside a
+    // transaction, and after migrating since the rebuilds drop tables.
+    connection.execute("PRAGMA foreign_keys = ON")?;
+    Ok(connection)
+}
+
+/// Opens an existing database for reading only. Its schema must be current,
+/// since bringing it up to date would write.
+///
+/// sqlite3 0.24 can only open with `SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE`,
+/// so a missing file is refused here rather than created, a write-protected
+/// one is opened read-only by SQLite itself, and `PRAGMA query_only` turns
+/// away writes to the others.
+pub fn init_readonly(db_file: &str) -> Result<Connection> {
+    if !std::path::Path::new(db_file).is_file() {
+        return Err(Error::NotFound(format!("database {db_file:?}")));
+    }
+    let mut connection = sqlite3::open(db_file)?;
+    connection.set_busy_timeout(BUSY_TIMEOUT_MS)?;
+    let current = version(&connection)?;
+    if current != SCHEMA_VERSION {
+        return Err(Error::SchemaMismatch(format!(
+            "database schema version {current} is not the supported version {SCHEMA_VERSION}")));
+    }
+    check_schema(&connection)?;
+    connection.execute("PRAGMA query_only = ON")?;
+    Ok(connection)
+}
+
+/// The schema version recorded in the database.
+pub fn version(connection: &Connection) -> Result<i64> {
+    let mut cursor = connection.prepare("PRAGMA user_version")?.cursor();
+    match cursor.next()? {
+        Some(row) => Ok(row[0].as_integer().unwrap_or(0)),
+        None => Ok(0),
+    }
+}
+
+// Applies all pending migrations in one transaction, so a failing step leaves
+// the database at the version it was opened with.
+fn migrate(connection: &Connection) -> Result<()> {
+    let current = version(connection)?;
+    if current > SCHEMA_VERSION {
+        return Err(Error::SchemaMismatch(format!(
+            "database schema version {current} is newer than supported version {SCHEMA_VERSION}")));
+    }
+    if current == SCHEMA_VERSION {
+        return Ok(())
+    }
+    retry(|| Ok(connection.execute("BEGIN IMMEDIATE")?))?;
+    // another process may have migrated while we waited for the lock
+    let current = version(connection)?;
+    let mut ret = Ok(());
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current.max(0) as usize) {
+        ret = connection.execute(migration)
+            .and_then(|_| connection.execute(format!("PRAGMA user_version = {}", i + 1)));
+        if ret.is_err() { break }
+    }
+    match ret {
+        Ok(()) => Ok(connection.execute("COMMIT")?),
+        Err(err) => {
+            connection.execute("ROLLBACK")?;
+            Err(err.into())
+        },
+    }
+}
+
+// Tables created by an incompatible version survive CREATE TABLE IF NOT EXISTS,
+// so make sure every column we query is actually there.
+fn check_schema(connection: &Connection) -> Result<()> {
+    let tables = [
+        ("tags", "id, name, inode_num, create_at"),
+        ("inodes", "id, device, number, btime, tag_num, create_at"),
+        ("relation_tag_inode", "id, tag_id, inode_id, create_at"),
+        ("file", "id, md5, sha256, path, create_at"),
+        ("relation_tag_file", "id, tag_id, file_id, create_at"),
+        ("paths", "id, inode_id, path, update_at"),
+    ];
+    for (table, columns) in tables {
+        if let Err(err) = connection.prepare(format!("SELECT {columns} FROM {table} LIMIT 0")) {
+            return Err(Error::SchemaMismatch(format!("table {table}: {err}")));
+        }
+    }
+    Ok(())
+}
+
+/// Device and inode numbers are u64, SQLite integers i64: they are stored as
+/// the i64 with the same bits, so values above i64::MAX are negative in the
+/// database. Always bind them through here, never as SQL text, where SQLite
+/// would read values above i64::MAX as REAL and lose precision.
+pub(crate) fn to_int(n: u64) -> i64 {
+    n as i64
+}
+
+/// Reverses [`to_int`].
+pub(crate) fn from_int(value: &Value) -> u64 {
+    value.as_integer().unwrap_or(0) as u64
+}
+
+/// Runs `f` again while it fails with a busy error ([`Error::is_busy`]),
+/// backing off a little longer each time. Only for operations that do
+/// nothing when they fail, like taking a lock.
+pub(crate) fn retry<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(err) if err.is_busy() && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                std::thread::sleep(std::time::Duration::from_millis(50 << attempt));
+            },
+            ret => return ret,
+        }
+    }
+}
+
+/// Rows of a query. Unlike sqlite3::Cursor, which keeps the column types of
+/// the first row (reading later NULLs as 0 and values after a NULL as NULL),
+/// every value is read with its own type, so use this for nullable columns.
+pub(crate) struct Rows<'l> {
+    statement: Statement<'l>,
+}
+
+impl<'l> Rows<'l> {
+    pub(crate) fn new(connection: &'l Connection, sql_str: &str, sql_args: &[Value]) -> Result<Rows<'l>> {
+        let mut statement = connection.prepare(sql_str)?;
+        for (i, value) in sql_args.iter().enumerate() {
+            statement.bind(i + 1, value)?;
+        }
+        Ok(Rows { statement })
+    }
+
+    pub(crate) fn next(&mut self) -> Result<Option<Vec<Value>>> {
+        match self.statement.next()? {
+            State::Done => Ok(None),
+            State::Row => {
+                let mut row = Vec::with_capacity(self.statement.columns());
+                for i in 0..self.statement.columns() {
+                    row.push(self.statement.read::<Value>(i)?);
+                }
+                Ok(Some(row))
+            },
+        }
+    }
+}
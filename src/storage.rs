@@ -0,0 +1,125 @@
+//! The operations a [`TagStore`](crate::TagStore) needs from its storage, so
+//! tags can live somewhere else than an SQLite database, see [`memory`](crate::memory).
+
+use std::cell::RefCell;
+use sqlite3::Connection;
+use crate::{Iter, INode, InodeTags, Query, Result, Stats, Tag};
+
+/// Storage of tags, inodes and the relations between them. Implementations
+/// behave like the SQLite one: the same errors for the same input, and the
+/// same order of results.
+pub trait Storage {
+    /// Starts a transaction, or a nested one inside another.
+    fn begin(&self) -> Result<()>;
+    /// Makes the writes since the matching [`Storage::begin`] permanent, or
+    /// part of the enclosing transaction if nested.
+    fn commit(&self) -> Result<()>;
+    /// Undoes the writes since the matching [`Storage::begin`].
+    fn rollback(&self) -> Result<()>;
+
+    /// See [`crate::add`].
+    fn add(&self, inodes: &[INode], tag_names: &[&str]) -> Result<()>;
+    /// See [`crate::remove`].
+    fn remove(&self, inodes: &[INode], tag_names: &[&str], prune: bool) -> Result<()>;
+    /// See [`crate::rename_tag`].
+    fn rename_tag(&self, old: &str, new: &str) -> Result<()>;
+    /// See [`crate::merge_tags`].
+    fn merge_tags(&self, sources: &[&str], dst: &str) -> Result<()>;
+
+    /// Inodes tagged with all of `tag_names`, in the order they were first tagged.
+    fn get_inodes(&self, tag_names: &[&str]) -> Result<Iter<'_, INode>>;
+    /// Inodes matching `query`, in the order they were first tagged.
+    fn search(&self, query: &Query) -> Result<Iter<'_, INode>>;
+    /// Tags of `inode`, in the order the tags were created, see [`crate::get_tags`].
+    fn get_tags(&self, inode: &INode) -> Result<InodeTags<Iter<'_, String>>>;
+    /// All tag names, sorted.
+    fn list_tags(&self) -> Result<Iter<'_, String>>;
+    /// All tags, most used first, then by name.
+    fn list_tag_counts(&self) -> Result<Iter<'_, Tag>>;
+    fn stats(&self) -> Result<Stats>;
+}
+
+/// The SQLite database behind [`TagStore::open`](crate::TagStore::open).
+pub(crate) struct SqliteStorage {
+    pub(crate) connection: Connection,
+    // Whether each open transaction is nested, innermost last.
+    nested: RefCell<Vec<bool>>,
+}
+
+impl SqliteStorage {
+    pub(crate) fn new(connection: Connection) -> SqliteStorage {
+        SqliteStorage { connection, nested: RefCell::new(Vec::new()) }
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn begin(&self) -> Result<()> {
+        let nested = crate::begin(&self.connection)?;
+        self.nested.borrow_mut().push(nested);
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        let nested = self.nested.borrow_mut().pop().unwrap_or_default();
+        crate::commit(&self.connection, nested)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let nested = self.nested.borrow_mut().pop().unwrap_or_default();
+        crate::rollback(&self.connection, nested)
+    }
+
+    fn add(&self, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
+        crate::add(&self.connection, inodes, tag_names)
+    }
+
+    fn remove(&self, inodes: &[INode], tag_names: &[&str], prune: bool) -> Result<()> {
+        crate::remove(&self.connection, inodes, tag_names, prune)
+    }
+
+    fn rename_tag(&self, old: &str, new: &str) -> Result<()> {
+        crate::rename_tag(&self.connection, old, new)
+    }
+
+    fn merge_tags(&self, sources: &[&str], dst: &str) -> Result<()> {
+        crate::merge_tags(&self.connection, sources, dst)
+    }
+
+    fn get_inodes(&self, tag_names: &[&str]) -> Result<Iter<'_, INode>> {
+        crate::get_inodes_iter(&self.connection, tag_names)
+    }
+
+    fn search(&self, query: &Query) -> Result<Iter<'_, INode>> {
+        crate::search_iter(&self.connection, query)
+    }
+
+    fn get_tags(&self, inode: &INode) -> Result<InodeTags<Iter<'_, String>>> {
+        crate::get_tags_iter(&self.connection, inode)
+    }
+
+    fn list_tags(&self) -> Result<Iter<'_, String>> {
+        crate::list_tags_iter(&self.connection)
+    }
+
+    fn list_tag_counts(&self) -> Result<Iter<'_, Tag>> {
+        crate::list_tag_counts_iter(&self.connection)
+    }
+
+    fn stats(&self) -> Result<Stats> {
+        let mut cursor = self.connection.prepare("
+            SELECT (SELECT COUNT(*) FROM tags), (SELECT COUNT(*) FROM inodes),
+            (SELECT COUNT(*) FROM relation_tag_inode), (SELECT COUNT(*) FROM file),
+            (SELECT COUNT(*) FROM relation_tag_file), (SELECT COUNT(*) FROM paths)
+            ")?.cursor();
+        let Some(row) = cursor.next()? else { return Ok(Stats::default()) };
+        let n = |i: usize| row[i].as_integer().unwrap_or(0) as u64;
+        Ok(Stats {
+            tags: n(0),
+            inodes: n(1),
+            relations: n(2),
+            files: n(3),
+            file_relations: n(4),
+            paths: n(5),
+        })
+    }
+}
@@ -0,0 +1,210 @@
+//! A handle on a tag database, so callers need not deal with connections,
+//! schema setup or the storage engine behind it.
+
+use std::path::Path;
+use sqlite3::Connection;
+use crate::{content, gc, index, sql, Error, File, INode, InodeTags, Iter, Query, Result, Storage, Tag};
+use crate::index::PathEntry;
+use crate::walk::WalkOptions;
+use crate::storage::SqliteStorage;
+
+/// An open tag database. Every write is atomic, see [`TagStore::transaction`]
+/// to group several into one.
+pub struct TagStore {
+    backend: Backend,
+}
+
+enum Backend {
+    Sqlite(SqliteStorage),
+    Other(Box<dyn Storage>),
+}
+
+/// Row counts of a [`TagStore`], see [`TagStore::stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub tags: u64,
+    /// Tagged inodes.
+    pub inodes: u64,
+    /// Inode and tag pairs.
+    pub relations: u64,
+    /// Tagged contents, see [`content`].
+    pub files: u64,
+    /// Content and tag pairs.
+    pub file_relations: u64,
+    /// Recorded paths, see [`index`].
+    pub paths: u64,
+}
+
+impl TagStore {
+    /// Opens the database at `path`, creating it if needed and bringing its
+    /// schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> Result<TagStore> {
+        Ok(TagStore::sqlite(sql::init(&path.as_ref().to_string_lossy())?))
+    }
+
+    /// A fresh database that lives as long as the returned store.
+    pub fn open_in_memory() -> Result<TagStore> {
+        TagStore::open(":memory:")
+    }
+
+    /// Opens an existing database without ever writing to it; every write
+    /// method fails. The schema must already be current, since migrating
+    /// would write.
+    pub fn open_readonly(path: impl AsRef<Path>) -> Result<TagStore> {
+        Ok(TagStore::sqlite(sql::init_readonly(&path.as_ref().to_string_lossy())?))
+    }
+
+    /// A store kept in `storage`, e.g. a [`MemoryStorage`](crate::memory::MemoryStorage).
+    /// Only the operations of the [`Storage`] trait are supported; the content,
+    /// path index and gc methods fail with [`Error::Unsupported`].
+    pub fn with_storage(storage: impl Storage + 'static) -> TagStore {
+        TagStore { backend: Backend::Other(Box::new(storage)) }
+    }
+
+    fn sqlite(connection: Connection) -> TagStore {
+        TagStore { backend: Backend::Sqlite(SqliteStorage::new(connection)) }
+    }
+
+    fn storage(&self) -> &dyn Storage {
+        match &self.backend {
+            Backend::Sqlite(storage) => storage,
+            Backend::Other(storage) => storage.as_ref(),
+        }
+    }
+
+    // The database behind the operations beyond the Storage trait.
+    fn connection(&self, what: &str) -> Result<&Connection> {
+        match &self.backend {
+            Backend::Sqlite(storage) => Ok(&storage.connection),
+            Backend::Other(_) => Err(Error::Unsupported(what.to_string())),
+        }
+    }
+
+    /// Runs `f` in one transaction, see [`crate::transaction`].
+    pub fn transaction<T>(&self, f: impl FnOnce(&TagStore) -> Result<T>) -> Result<T> {
+        self.storage().begin()?;
+        match f(self) {
+            Ok(ret) => {
+                self.storage().commit()?;
+                Ok(ret)
+            },
+            Err(err) => {
+                self.storage().rollback()?;
+                Err(err)
+            },
+        }
+    }
+
+    /// See [`crate::add`].
+    pub fn add(&self, inodes: &[INode], tag_names: &[&str]) -> Result<()> {
+        self.storage().add(inodes, tag_names)
+    }
+
+    /// See [`crate::remove`].
+    pub fn remove(&self, inodes: &[INode], tag_names: &[&str], prune: bool) -> Result<()> {
+        self.storage().remove(inodes, tag_names, prune)
+    }
+
+    /// See [`crate::rename_tag`].
+    pub fn rename_tag(&self, old: &str, new: &str) -> Result<()> {
+        self.storage().rename_tag(old, new)
+    }
+
+    /// See [`crate::merge_tags`].
+    pub fn merge_tags(&self, sources: &[&str], dst: &str) -> Result<()> {
+        self.storage().merge_tags(sources, dst)
+    }
+
+    /// The inodes tagged with every tag in `tag_names`.
+    pub fn get_inodes(&self, tag_names: &[&str]) -> Result<Vec<INode>> {
+        self.get_inodes_iter(tag_names)?.collect()
+    }
+
+    pub fn get_inodes_iter(&self, tag_names: &[&str]) -> Result<Iter<'_, INode>> {
+        self.storage().get_inodes(tag_names)
+    }
+
+    /// The inodes whose tags satisfy `query`.
+    pub fn search(&self, query: &Query) -> Result<Vec<INode>> {
+        self.search_iter(query)?.collect()
+    }
+
+    pub fn search_iter(&self, query: &Query) -> Result<Iter<'_, INode>> {
+        self.storage().search(query)
+    }
+
+    /// See [`crate::get_tags`].
+    pub fn get_tags(&self, inode: &INode) -> Result<InodeTags> {
+        self.get_tags_iter(inode)?.collect()
+    }
+
+    pub fn get_tags_iter(&self, inode: &INode) -> Result<InodeTags<Iter<'_, String>>> {
+        self.storage().get_tags(inode)
+    }
+
+    pub fn list_tags(&self) -> Result<Vec<String>> {
+        self.list_tags_iter()?.collect()
+    }
+
+    pub fn list_tags_iter(&self) -> Result<Iter<'_, String>> {
+        self.storage().list_tags()
+    }
+
+    /// Every tag with its number of inodes, most used first.
+    pub fn list_tag_counts(&self) -> Result<Vec<Tag>> {
+        self.list_tag_counts_iter()?.collect()
+    }
+
+    pub fn list_tag_counts_iter(&self) -> Result<Iter<'_, Tag>> {
+        self.storage().list_tag_counts()
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        self.storage().stats()
+    }
+
+    /// See [`content::add`].
+    pub fn add_content(&self, files: &[File], tag_names: &[&str]) -> Result<()> {
+        content::add(self.connection("content")?, files, tag_names)
+    }
+
+    /// See [`content::remove`].
+    pub fn remove_content(&self, files: &[File], tag_names: &[&str], prune: bool) -> Result<()> {
+        content::remove(self.connection("content")?, files, tag_names, prune)
+    }
+
+    /// See [`content::search`].
+    pub fn search_content(&self, query: &Query) -> Result<Vec<File>> {
+        content::search(self.connection("content")?, query)
+    }
+
+    /// See [`content::get_tags`].
+    pub fn get_content_tags(&self, file: &File) -> Result<Vec<String>> {
+        content::get_tags(self.connection("content")?, file)
+    }
+
+    /// See [`index::record`].
+    pub fn record_paths(&self, entries: &[PathEntry]) -> Result<()> {
+        index::record(self.connection("path index")?, entries)
+    }
+
+    /// See [`index::paths`].
+    pub fn paths(&self, inode: &INode) -> Result<Vec<String>> {
+        index::paths(self.connection("path index")?, inode)
+    }
+
+    /// See [`index::locate`].
+    pub fn locate(&self, inodes: &[INode]) -> Result<(Vec<PathEntry>, Vec<INode>)> {
+        index::locate(self.connection("path index")?, inodes)
+    }
+
+    /// See [`gc::gc`].
+    pub fn gc(&self, roots: &[&str], options: &WalkOptions, dry_run: bool) -> Result<gc::Report> {
+        gc::gc(self.connection("gc")?, roots, options, dry_run)
+    }
+
+    /// See [`gc::tagged_mount_points`].
+    pub fn tagged_mount_points(&self) -> Result<Vec<String>> {
+        gc::tagged_mount_points(self.connection("gc")?)
+    }
+}
@@ -0,0 +1,451 @@
+//! Directory walking shared by the operations that scan the filesystem.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How [`walk`] goes through a tree. The default walks everything on the
+/// root's device.
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Enter directories on other devices than the root's.
+    pub cross_dev: bool,
+    /// Patterns of entries to leave out, in gitignore syntax relative to the
+    /// root, e.g. `.git/`, `*.o` or `/build`. They win over ignore files.
+    pub exclude: Vec<String>,
+    /// Names of the ignore files, e.g. `.taginodeignore`, read in every
+    /// directory entered. Their patterns apply below that directory.
+    pub ignore_files: Vec<String>,
+    /// Deepest level visited, the root being at depth 0.
+    pub max_depth: Option<usize>,
+}
+
+// One pattern of an ignore file or of WalkOptions::exclude.
+#[derive(Debug, Clone)]
+struct Rule {
+    // The directory the pattern is relative to, as a path from the root.
+    base: String,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    // With a `/` other than at the end, a pattern matches the path from
+    // base; otherwise the name of an entry at any depth.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line).to_string();
+        if pattern.is_empty() {
+            return None
+        }
+        Some(Rule { base: base.to_string(), pattern, negated, dir_only, anchored })
+    }
+
+    // Whether the rule matches the entry at path, relative to the root.
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false
+        }
+        let path = match self.base.as_str() {
+            "" => path,
+            base => match path.strip_prefix(base).and_then(|path| path.strip_prefix('/')) {
+                Some(path) => path,
+                None => return false,
+            },
+        };
+        if self.anchored {
+            let pattern: Vec<&str> = self.pattern.split('/').collect();
+            let path: Vec<&str> = path.split('/').collect();
+            path_match(&pattern, &path)
+        } else {
+            glob_match(&self.pattern, path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+}
+
+// Glob matching by path segments, where a `**` segment matches any number of them.
+fn path_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| path_match(rest, &path[i..])),
+        Some((segment, rest)) => !path.is_empty() && glob_match(segment, path[0]) && path_match(rest, &path[1..]),
+    }
+}
+
+// Whether the last rule that matches the entry ignores it, the exclude rules
+// coming after those of the ignore files.
+fn ignored(rules: &[Rule], exclude: &[Rule], path: &str, is_dir: bool) -> bool {
+    exclude.iter().rev().chain(rules.iter().rev())
+        .find(|rule| rule.matches(path, is_dir))
+        .is_some_and(|rule| !rule.negated)
+}
+
+// An entry to visit, and to enter if it is a directory.
+struct Job {
+    path: String,
+    // The path from the root.
+    rel: String,
+    metadata: Metadata,
+    depth: usize,
+    root_dev: u64,
+    // The rules of the ignore files above the entry.
+    rules: Arc<Vec<Rule>>,
+}
+
+// What the walks of one set of options share.
+struct Walker<'a> {
+    options: &'a WalkOptions,
+    exclude: Vec<Rule>,
+}
+
+impl Walker<'_> {
+    fn new(options: &WalkOptions) -> Walker<'_> {
+        Walker { options, exclude: options.exclude.iter().filter_map(|line| Rule::parse(line, "")).collect() }
+    }
+
+    fn root(&self, root: &str, error: &mut dyn FnMut(&str, io::Error)) -> Option<Job> {
+        match fs::symlink_metadata(root) {
+            Ok(metadata) => Some(Job {
+                path: root.to_string(),
+                rel: String::new(),
+                root_dev: metadata.dev(),
+                metadata,
+                depth: 0,
+                rules: Arc::default(),
+            }),
+            Err(err) => {
+                error(root, err);
+                None
+            },
+        }
+    }
+
+    // Whether job, already visited, is a directory to enter.
+    fn enters(&self, job: &Job, skipped: &mut usize) -> bool {
+        if !job.metadata.is_dir() || (!self.options.cross_dev && job.metadata.dev() != job.root_dev) {
+            return false
+        }
+        if self.options.max_depth.is_some_and(|max_depth| job.depth >= max_depth) {
+            *skipped += 1;
+            return false
+        }
+        true
+    }
+
+    // The entries of directory job that the rules leave in.
+    fn children(&self, job: &Job, skipped: &mut usize, error: &mut dyn FnMut(&str, io::Error)) -> Vec<Job> {
+        let paths = match fs::read_dir(&job.path) {
+            Ok(paths) => paths,
+            Err(err) => {
+                error(&job.path, err);
+                return Vec::new()
+            },
+        };
+        let mut rules = job.rules.clone();
+        self.read_ignore_files(&job.path, &job.rel, &mut rules, error);
+
+        let mut children = Vec::new();
+        for path in paths {
+            let entry = match path {
+                Ok(entry) => entry,
+                Err(err) => {
+                    error(&job.path, err);
+                    continue
+                },
+            };
+            let p = entry.path();
+            let p = p.to_string_lossy();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let rel = if job.rel.is_empty() { name.into_owned() } else { format!("{}/{name}", job.rel) };
+            match fs::symlink_metadata(&*p) {
+                Ok(metadata) if ignored(&rules, &self.exclude, &rel, metadata.is_dir()) => *skipped += 1,
+                Ok(metadata) => children.push(Job {
+                    path: p.into_owned(),
+                    rel,
+                    metadata,
+                    depth: job.depth + 1,
+                    root_dev: job.root_dev,
+                    rules: rules.clone(),
+                }),
+                Err(err) => error(&p, err),
+            }
+        }
+        children
+    }
+
+    // Adds the rules of the ignore files in directory dir, at rel from the root.
+    fn read_ignore_files(&self, dir: &str, rel: &str, rules: &mut Arc<Vec<Rule>>, error: &mut dyn FnMut(&str, io::Error)) {
+        for name in &self.options.ignore_files {
+            let path = format!("{dir}/{name}");
+            match fs::read_to_string(&path) {
+                Ok(text) => Arc::make_mut(rules).extend(text.lines().filter_map(|line| Rule::parse(line, rel))),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => error(&path, err),
+            }
+        }
+    }
+
+    fn walk(&self, job: Job, visit: &mut dyn FnMut(&str, &Metadata) -> bool, error: &mut dyn FnMut(&str, io::Error), skipped: &mut usize) {
+        if !visit(&job.path, &job.metadata) || !self.enters(&job, skipped) {
+            return
+        }
+        for child in self.children(&job, skipped, error) {
+            self.walk(child, visit, error, skipped);
+        }
+    }
+}
+
+/// Walks the tree under `root` depth-first without following symbolic links,
+/// calling `visit` for every entry including `root` itself; directories are
+/// entered only if it returns true. Unless `options.cross_dev` is set,
+/// directories on another device than `root` are visited but not entered.
+/// Entries that cannot be read are passed to `error`. Returns how many
+/// entries were left out by the rules and depth of `options`, a directory not
+/// entered for its depth counting as one.
+pub fn walk(
+    root: &str,
+    options: &WalkOptions,
+    visit: &mut dyn FnMut(&str, &Metadata) -> bool,
+    error: &mut dyn FnMut(&str, io::Error),
+) -> usize {
+    let walker = Walker::new(options);
+    let mut skipped = 0;
+    if let Some(job) = walker.root(root, error) {
+        walker.walk(job, visit, error, &mut skipped);
+    }
+    skipped
+}
+
+/// The rules of walks from one root, for entries found some other way, e.g.
+/// through the path index, to be left out as a walk would leave them out.
+pub struct Matcher<'a> {
+    walker: Walker<'a>,
+    root: String,
+    root_dev: u64,
+}
+
+impl Matcher<'_> {
+    pub fn new<'a>(root: &str, options: &'a WalkOptions) -> io::Result<Matcher<'a>> {
+        let root_dev = fs::symlink_metadata(root)?.dev();
+        Ok(Matcher { walker: Walker::new(options), root: root.to_string(), root_dev })
+    }
+
+    /// Whether a walk from the root would visit the entry at `rel`, a path
+    /// from the root `depth` levels down, which `is_dir` tells is a directory
+    /// or not. The directories above it are read for their device and ignore
+    /// files; one that cannot be read is taken to leave the entry out.
+    pub fn matches(&self, rel: &str, depth: usize, is_dir: bool) -> bool {
+        if rel.is_empty() {
+            return true
+        }
+        if self.walker.options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return false
+        }
+        let mut rules = Arc::default();
+        let (mut dir, mut dir_rel) = (self.root.clone(), String::new());
+        let names: Vec<&str> = rel.split('/').collect();
+        for (i, name) in names.iter().enumerate() {
+            let entered = fs::symlink_metadata(&dir).is_ok_and(|metadata| {
+                metadata.is_dir() && (self.walker.options.cross_dev || metadata.dev() == self.root_dev)
+            });
+            if !entered {
+                return false
+            }
+            let mut failed = false;
+            self.walker.read_ignore_files(&dir, &dir_rel, &mut rules, &mut |_, _| failed = true);
+            if failed {
+                return false
+            }
+            dir_rel = if dir_rel.is_empty() { name.to_string() } else { format!("{dir_rel}/{name}") };
+            if ignored(&rules, &self.walker.exclude, &dir_rel, i + 1 < names.len() || is_dir) {
+                return false
+            }
+            dir = format!("{dir}/{name}");
+        }
+        true
+    }
+}
+
+/// Like [`walk`] over each of `roots`, but on `threads` threads, each taking
+/// directories from the others when it runs out. `visit` and `error` are
+/// called from all of them, in no particular order.
+pub fn walk_parallel(
+    roots: &[&str],
+    options: &WalkOptions,
+    threads: usize,
+    visit: &(dyn Fn(&str, &Metadata) -> bool + Sync),
+    error: &(dyn Fn(&str, io::Error) + Sync),
+) -> usize {
+    let walker = Walker::new(options);
+    let threads = threads.max(1);
+    // Each thread works at the back of its own queue and steals from the
+    // front of the others', where the directories nearest a root are.
+    let queues: Vec<Mutex<VecDeque<Job>>> = (0..threads).map(|_| Mutex::default()).collect();
+    for (i, root) in roots.iter().enumerate() {
+        if let Some(job) = walker.root(root, &mut |path, err| error(path, err)) {
+            lock(&queues[i % threads]).push_back(job);
+        }
+    }
+    // Jobs queued or being worked on; none left means the walk is done.
+    let pending = AtomicUsize::new(queues.iter().map(|queue| lock(queue).len()).sum());
+    let skipped = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    let work = |i: usize| {
+        // Stops the other threads if visit or error panics on this one.
+        let _guard = PanicGuard(&failed);
+        let mut idle = 0;
+        while !failed.load(Ordering::Relaxed) {
+            // Never hold a queue while locking another one: two threads
+            // stealing from each other would deadlock.
+            let own = lock(&queues[i]).pop_back();
+            let job = own.or_else(|| (1..threads).find_map(|j| lock(&queues[(i + j) % threads]).pop_front()));
+            let Some(job) = job else {
+                if pending.load(Ordering::SeqCst) == 0 {
+                    break
+                }
+                idle += 1;
+                if idle < 64 {
+                    thread::yield_now();
+                } else {
+                    thread::sleep(Duration::from_micros(100));
+                }
+                continue
+            };
+            idle = 0;
+            let mut n = 0;
+            if visit(&job.path, &job.metadata) && walker.enters(&job, &mut n) {
+                let children = walker.children(&job, &mut n, &mut |path, err| error(path, err));
+                pending.fetch_add(children.len(), Ordering::SeqCst);
+                lock(&queues[i]).extend(children);
+            }
+            skipped.fetch_add(n, Ordering::Relaxed);
+            pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    };
+    thread::scope(|scope| {
+        for i in 0..threads {
+            let work = &work;
+            scope.spawn(move || work(i));
+        }
+    });
+    skipped.into_inner()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct PanicGuard<'a>(&'a AtomicBool);
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of
+/// characters, `?` any one, and `[...]` one of a set such as `[a-z]` or
+/// `[!0-9]`. A `\\` makes the next character literal.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Where to resume after the last `*`: its pattern index and the name index it stood for.
+    let (mut pi, mut ni, mut star) = (0, 0, None);
+    while ni < n.len() {
+        let step = match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ni));
+                pi += 1;
+                continue
+            },
+            Some('?') => Some(1),
+            Some('[') => class(&p[pi..], n[ni]),
+            Some('\\') if pi + 1 < p.len() => (p[pi+1] == n[ni]).then_some(2),
+            Some(c) => (*c == n[ni]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(step), _) => {
+                pi += step;
+                ni += 1;
+            },
+            (None, Some((star_pi, star_ni))) => {
+                pi = star_pi + 1;
+                ni = star_ni + 1;
+                star = Some((star_pi, ni));
+            },
+            (None, None) => return false,
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+// The length of the `[...]` class at the start of p if it matches c. An
+// unclosed `[` is a literal.
+fn class(p: &[char], c: char) -> Option<usize> {
+    let negated = matches!(p.get(1), Some('!' | '^'));
+    let start = if negated { 2 } else { 1 };
+    // a `]` right after the `[` or `[!` is part of the set
+    let Some(end) = p.iter().skip(start + 1).position(|c| *c == ']').map(|i| i + start + 1) else {
+        return (c == '[').then_some(1)
+    };
+    let set = &p[start..end];
+    let mut matched = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i+1] == '-' {
+            matched |= set[i] <= c && c <= set[i+2];
+            i += 3;
+        } else {
+            matched |= set[i] == c;
+            i += 1;
+        }
+    }
+    (matched != negated).then_some(end + 1)
+}
+
+/// Mount points listed in /proc/self/mounts.
+pub fn mount_points() -> io::Result<Vec<String>> {
+    let mounts = fs::read_to_string("/proc/self/mounts")?;
+    Ok(mounts.lines().filter_map(|line| line.split(' ').nth(1)).map(unescape_mount).collect())
+}
+
+// /proc/self/mounts escapes space, tab, newline and backslash as \ooo.
+fn unescape_mount(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'\\' && i + 3 < b.len() && b[i+1..i+4].iter().all(|c| (b'0'..=b'7').contains(c)) {
+            out.push(u32::from_str_radix(&s[i+1..i+4], 8).unwrap_or(0) as u8);
+            i += 4;
+        } else {
+            out.push(b[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -1,72 +1,95 @@
-use taginode::INode;
+use taginode::{INode, InodeTags};
 
 #[test]
 fn t() {
-    let connection = taginode::sql::init(":memory:");
-    {
-	let inodes = vec![
-		INode{ device: 16777220, number: 12951634006, btime: None },
-		INode{ device: 16777220, number: 12951634036, btime: None },
-	];
-	taginode::add(&connection, &inodes, &["ikun", "basketball", "man"]);
-    }
-    {
-	let inodes = vec![
-		INode{ device: 16777221, number: 12951634006, btime: None },
-		INode{ device: 16777220, number: 12951634036, btime: Some(1665935055) },
-	];
-	taginode::add(&connection, &inodes, &["basketball", "chicken"]);
-    }
+    for store in stores() {
+        {
+            let inodes = vec![
+                INode{ device: 16777220, number: 12951634006, btime: None },
+                INode{ device: 16777220, number: 12951634036, btime: None },
+            ];
+            store.add(&inodes, &["ikun", "basketball", "man"]).unwrap();
+        }
+        {
+            let inodes = vec![
+                INode{ device: 16777221, number: 12951634006, btime: None },
+                INode{ device: 16777220, number: 12951634036, btime: Some(1665935055) },
+            ];
+            store.add(&inodes, &["basketball", "chicken"]).unwrap();
+        }
+
+        let tag_names = vec![ "ikun", "basketball", "ikun", "chicken"];
+        let inodes = store.get_inodes(&tag_names).unwrap();
+        println!("{:?}", inodes);
+        let expect = [INode { device:16777220, number: 12951634036, btime: Some(1665935055) }];
+        for (i, e) in inodes.iter().enumerate() {
+            assert_eq!(e.device, expect[i].device);
+            assert_eq!(e.number, expect[i].number);
+            assert_eq!(e.btime,  expect[i].btime);
+        }
+
+        {
+            let tag_names = store.list_tags().unwrap();
+            let expect = ["basketball", "chicken", "ikun", "man"];
+            let expect: Vec<String> = expect.iter().map(|s| s.to_string()).collect();
+            assert_eq!(expect, tag_names);
+        }
 
-    let tag_names = vec![ "ikun", "basketball", "ikun", "chicken"];
-    let inodes = taginode::get_inodes(&connection, &tag_names);
-	println!("{:?}", inodes);
-    let expect = vec![INode { device:16777220, number: 12951634036, btime: Some(1665935055) }];
-    for (i, e) in inodes.iter().enumerate() {
-    	assert_eq!(e.device, expect[i].device);
-    	assert_eq!(e.number, expect[i].number);
-    	assert_eq!(e.btime,  expect[i].btime);
+        {
+            let tags = store.get_tags(
+                &INode{ device: 16777220, number: 12951634036, btime: Some(1665935055) },
+            ).unwrap();
+            let expect = [ "ikun", "basketball", "man", "chicken"];
+            let expect: Vec<String> = expect.iter().map(|s| s.to_string()).collect();
+            assert_eq!(InodeTags::Tagged(expect), tags)
+        }
     }
+}
 
-    {
-        let tag_names = taginode::list_tags(&connection);
-        let expect = vec!["basketball", "chicken", "ikun", "man"];
-        let expect: Vec<String> = expect.iter().map(|s| s.to_string()).collect();
-        assert_eq!(expect, tag_names);
+#[test]
+fn t_error() {
+    let connection = taginode::sql::init(":memory:").unwrap();
+    let inodes = vec![INode{ device: 16777220, number: 12951634006, btime: None }];
+    match taginode::add(&connection, &inodes, &["ikun", ""]) {
+        Err(taginode::Error::InvalidTag(name)) => assert_eq!(name, ""),
+        ret => panic!("expect InvalidTag, got {:?}", ret),
     }
+    assert!(taginode::list_tags(&connection).unwrap().is_empty());
 
+    let db = std::env::temp_dir().join(format!("taginode_test_error_{}.db", std::process::id()));
+    let db = db.to_str().unwrap();
     {
-	let tags = taginode::get_tags(&connection, 
-		INode{ device: 16777220, number: 12951634036, btime: Some(1665935055) },
-	);
-	let expect = vec![ "ikun", "basketball", "man", "chicken"];
-	let expect: Vec<String> = expect.iter().map(|s| s.to_string()).collect();
-	assert_eq!(expect, tags)
+        let connection = taginode::sql::init(db).unwrap();
+        connection.execute("DROP TABLE tags; CREATE TABLE tags (id INTEGER PRIMARY KEY)").unwrap();
     }
-
+    match taginode::sql::init(db) {
+        Err(taginode::Error::SchemaMismatch(_)) => (),
+        ret => panic!("expect SchemaMismatch, got {:?}", ret.err()),
+    }
+    std::fs::remove_file(db).unwrap();
 }
 
 #[test]
 fn t_usage() {
-	use std::collections::{BTreeMap};
-	use taginode::opt::OptArg;
+    use std::collections::{BTreeMap};
+    use taginode::opt::{Opt, OptArg};
     let opt_check = BTreeMap::from([
-        (b'f', (OptArg::Mandatory("/.taginode.db"), "-f <db>        specify db path to store data, default ~/.taginode.db"  )),
-        (b'd', (            OptArg::Mandatory("."), "-d <directory> specify path to search file by tags, default \".\""     )),
-        (b'v', (                      OptArg::None, "-v             verbose"                                                )),
+        (b'f', Opt { arg: OptArg::Mandatory("/.taginode.db"), value: "<db>", help: "specify db path to store data, default ~/.taginode.db", ..Opt::default() }),
+        (b'd', Opt { arg: OptArg::Mandatory("."), value: "<directory>", help: "specify path to search file by tags, default \".\"", ..Opt::default() }),
+        (b'v', Opt { help: "verbose", ..Opt::default() }),
     ]);
-    let usage = taginode::opt::usage(&opt_check);
+    let usage = taginode::opt::usage(&opt_check, None);
 	println!("{usage}");
 }
 
 #[test]
 fn t_opt() {
-	use std::collections::{BTreeMap};
-	use taginode::opt::OptArg;
+    use std::collections::{BTreeMap};
+    use taginode::opt::{Opt, OptArg};
     let opt_check = BTreeMap::from([
-        (b'f', (OptArg::Mandatory("/.taginode.db"), "-f <db>        specify db path to store data, default ~/.taginode.db"  )),
-        (b'd', (            OptArg::Mandatory("."), "-d <directory> specify path to search file by tags, default \".\""     )),
-        (b'v', (                      OptArg::None, "-v             verbose"                                                )),
+        (b'f', Opt { arg: OptArg::Mandatory("/.taginode.db"), value: "<db>", help: "specify db path to store data, default ~/.taginode.db", ..Opt::default() }),
+        (b'd', Opt { arg: OptArg::Mandatory("."), value: "<directory>", help: "specify path to search file by tags, default \".\"", ..Opt::default() }),
+        (b'v', Opt { help: "verbose", ..Opt::default() }),
     ]);
 
     let args: Vec<String> = vec![
@@ -87,4 +110,1087 @@ fn t_opt() {
 		
 	println!("{:?}", options);
 	println!("{:?}", operands);
-}
\ No newline at end of file
+}
+#[test]
+fn t_remove() {
+    for store in stores() {
+        let inodes = vec![
+            INode{ device: 16777220, number: 12951634006, btime: None },
+            INode{ device: 16777220, number: 12951634036, btime: None },
+        ];
+        store.add(&inodes, &["ikun", "basketball", "man"]).unwrap();
+
+        store.remove(&inodes[..1], &["ikun", "man"], false).unwrap();
+        let tags = store.get_tags(
+            &INode{ device: 16777220, number: 12951634006, btime: None },
+        ).unwrap();
+        assert_eq!(tags, InodeTags::Tagged(vec!["basketball".to_string()]));
+        assert_eq!(store.list_tags().unwrap().len(), 3);
+
+        store.remove(&inodes, &["man", "basketball"], true).unwrap();
+        let inodes = store.get_inodes(&["ikun"]).unwrap();
+        assert_eq!(inodes.len(), 1);
+        assert_eq!(inodes[0].number, 12951634036);
+        let expect: Vec<String> = ["ikun"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(store.list_tags().unwrap(), expect);
+    }
+}
+
+#[test]
+fn t_add_many() {
+    for store in stores() {
+        let inodes: Vec<INode> = (1..=3000).map(|number| {
+            INode{ device: 16777220, number, btime: None }
+        }).collect();
+        store.add(&inodes, &["ikun", "basketball", "man"]).unwrap();
+        assert_eq!(store.get_inodes(&["ikun", "man"]).unwrap().len(), 3000);
+
+        store.remove(&inodes[..2000], &["man"], false).unwrap();
+        assert_eq!(store.get_inodes(&["ikun", "man"]).unwrap().len(), 1000);
+    }
+}
+
+#[test]
+fn t_query() {
+    use taginode::Query;
+    use taginode::query::parse;
+    let tag = |name: &str| Box::new(Query::Tag(name.to_string()));
+
+    assert_eq!(parse("a,b").unwrap(), Query::And(tag("a"), tag("b")));
+    assert_eq!(parse("a or b and not c").unwrap(),
+        Query::Or(tag("a"), Box::new(Query::And(tag("b"), Box::new(Query::Not(tag("c")))))));
+    assert_eq!(parse("(a OR b), \"and\"").unwrap(),
+        Query::And(Box::new(Query::Or(tag("a"), tag("b"))), tag("and")));
+    // tags of a comma list are split on commas only
+    assert_eq!(parse("my photos, not,b").unwrap(),
+        Query::And(Box::new(Query::And(tag("my photos"), tag("not"))), tag("b")));
+    assert_eq!(parse("\"my photos\"").unwrap(), Query::Tag(String::from("my photos")));
+    assert_eq!(taginode::query::split_tags("x1, y1 ,z 1"), vec!["x1", "y1", "z 1"]);
+    assert_eq!(parse("a b").unwrap(), Query::Tag(String::from("a b")));
+    assert_eq!(parse("my photos").unwrap(), Query::Tag(String::from("my photos")));
+    for bad in ["", "a and", "(a or b", "\"a", "not", "a,", ",", "a,,b", "a b and c d"] {
+        assert!(matches!(parse(bad), Err(taginode::Error::InvalidQuery(_))), "{bad}");
+    }
+
+    for store in stores() {
+        let inode = |number| INode{ device: 16777220, number, btime: None };
+        store.add(&[inode(1), inode(2), inode(3), inode(4)], &["photo"]).unwrap();
+        store.add(&[inode(1), inode(5)], &["2023"]).unwrap();
+        store.add(&[inode(2)], &["2024", "private"]).unwrap();
+        store.add(&[inode(3)], &["2024"]).unwrap();
+
+        let search = |expr: &str| {
+            let mut numbers: Vec<u64> = store.search(&parse(expr).unwrap())
+                .unwrap().iter().map(|inode| inode.number).collect();
+            numbers.sort();
+            numbers
+        };
+        assert_eq!(search("photo and (2023 or 2024) and not private"), vec![1, 3]);
+        assert_eq!(search("photo,2024"), vec![2, 3]);
+        assert_eq!(search("not photo"), vec![5]);
+        assert_eq!(search("unknown or 2023"), vec![1, 5]);
+
+        store.add(&[inode(1), inode(3)], &["my photos"]).unwrap();
+        assert_eq!(search("my photos,2024"), vec![3]);
+        assert_eq!(search("my photos"), vec![1, 3]);
+        assert_eq!(search("\"my photos\" or private"), vec![1, 2, 3]);
+    }
+}
+
+#[test]
+fn t_index() {
+    let dir = temp_dir("index");
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    std::fs::write(&a, "a").unwrap();
+    std::fs::write(&b, "b").unwrap();
+    let inode = |path: &std::path::Path| INode::from_metadata(&std::fs::symlink_metadata(path).unwrap());
+    let (inode_a, inode_b) = (inode(&a), inode(&b));
+
+    let connection = taginode::sql::init(":memory:").unwrap();
+    taginode::add(&connection, &[inode_a.clone(), inode_b.clone()], &["ikun"]).unwrap();
+    let untagged = INode{ device: 16777220, number: 12951634006, btime: None };
+    taginode::index::record(&connection, &[
+        (inode_a.clone(), a.to_str().unwrap().to_string()),
+        (inode_b.clone(), b.to_str().unwrap().to_string()),
+        (untagged.clone(), a.to_str().unwrap().to_string()),
+    ]).unwrap();
+    assert_eq!(taginode::index::paths(&connection, &inode_a).unwrap(), vec![a.to_str().unwrap().to_string()]);
+    assert!(taginode::index::paths(&connection, &untagged).unwrap().is_empty());
+
+    let c = dir.join("c");
+    std::fs::rename(&b, &c).unwrap();
+    let (found, stale) = taginode::index::locate(&connection, &[inode_a.clone(), inode_b.clone()]).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].1, a.to_str().unwrap());
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].number, inode_b.number);
+    assert!(taginode::index::paths(&connection, &inode_b).unwrap().is_empty());
+}
+
+#[test]
+fn t_content() {
+    let dir = temp_dir("content");
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    std::fs::write(&a, "ikun").unwrap();
+    std::fs::write(&b, "basketball").unwrap();
+    let file_a = taginode::content::hash(a.to_str().unwrap()).unwrap();
+    let file_b = taginode::content::hash(b.to_str().unwrap()).unwrap();
+    assert_eq!(file_a.md5, "cd71184f9989c66b41df91c756ec5312");
+    assert_eq!(file_a.sha256.len(), 64);
+
+    let connection = taginode::sql::init(":memory:").unwrap();
+    taginode::content::add(&connection, &[file_a.clone(), file_b.clone()], &["ikun", "man"]).unwrap();
+    taginode::content::add(&connection, std::slice::from_ref(&file_b), &["basketball"]).unwrap();
+
+    // a copy has the same tags
+    let c = dir.join("c");
+    std::fs::copy(&b, &c).unwrap();
+    let file_c = taginode::content::hash(c.to_str().unwrap()).unwrap();
+    let expect: Vec<String> = ["ikun", "man", "basketball"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(taginode::content::get_tags(&connection, &file_c).unwrap(), expect);
+
+    let query = taginode::query::parse("ikun and not basketball").unwrap();
+    let files = taginode::content::search(&connection, &query).unwrap();
+    assert_eq!(files, vec![file_a.clone()]);
+
+    taginode::content::remove(&connection, &[file_a, file_b], &["man"], true).unwrap();
+    let expect: Vec<String> = ["ikun", "basketball"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(taginode::content::get_tags(&connection, &file_c).unwrap(), expect);
+    assert!(!taginode::list_tags(&connection).unwrap().contains(&"man".to_string()));
+
+    // paths are stored absolute
+    let relative = taginode::File { path: String::from("c"), ..file_c };
+    taginode::content::add(&connection, &[relative], &["copy"]).unwrap();
+    let files = taginode::content::search(&connection, &taginode::query::parse("copy").unwrap()).unwrap();
+    assert_eq!(files[0].path, std::env::current_dir().unwrap().join("c").to_str().unwrap());
+}
+
+#[test]
+fn t_rename_merge() {
+    for store in stores() {
+        let inode = |number| INode{ device: 16777220, number, btime: None };
+        store.add(&[inode(1), inode(2)], &["holiday"]).unwrap();
+        store.add(&[inode(2), inode(3)], &["vacation", "ikun"]).unwrap();
+
+        assert!(matches!(store.rename_tag("nothing", "x"), Err(taginode::Error::NotFound(_))));
+        assert!(matches!(store.rename_tag("ikun", "holiday"), Err(taginode::Error::TagExists(_))));
+        store.rename_tag("ikun", "chicken").unwrap();
+        assert_eq!(store.get_inodes(&["chicken"]).unwrap().len(), 2);
+
+        store.merge_tags(&["holiday", "vacation"], "trip").unwrap();
+        let mut numbers: Vec<u64> = store.get_inodes(&["trip"]).unwrap()
+            .iter().map(|inode| inode.number).collect();
+        numbers.sort();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        let expect: Vec<String> = ["chicken", "trip"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(store.list_tags().unwrap(), expect);
+
+        // merging into an existing tag keeps a single relation per inode
+        store.merge_tags(&["chicken"], "trip").unwrap();
+        assert_eq!(store.get_tags(&inode(2)).unwrap(), InodeTags::Tagged(vec!["trip".to_string()]));
+    }
+}
+
+#[test]
+fn t_gc() {
+    let dir = temp_dir("gc");
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    std::fs::write(&a, "a").unwrap();
+    std::fs::write(&b, "b").unwrap();
+    let inode = |path: &std::path::Path| INode::from_metadata(&std::fs::symlink_metadata(path).unwrap());
+    let (inode_a, mut inode_b) = (inode(&a), inode(&b));
+    let connection = taginode::sql::init(":memory:").unwrap();
+    let root = dir.to_str().unwrap();
+
+    // pretend b's inode number belonged to an older file, where btime is known
+    if let Some(btime) = inode_b.btime {
+        inode_b.btime = Some(btime - 100);
+        taginode::add(&connection, &[inode_a.clone(), inode_b.clone()], &["ikun", "man"]).unwrap();
+
+        let report = taginode::gc::gc(&connection, &[root], &Default::default(), true).unwrap();
+        assert_eq!(report.scanned, 3);
+        assert_eq!(report.reused.len(), 1);
+        assert_eq!(report.reused[0].number, inode_b.number);
+        assert_eq!(report.relations, 2);
+        assert_eq!(taginode::get_inodes(&connection, &["ikun"]).unwrap().len(), 2);
+
+        let report = taginode::gc::gc(&connection, &[root], &Default::default(), false).unwrap();
+        assert_eq!(report.relations, 2);
+        let inodes = taginode::get_inodes(&connection, &["ikun"]).unwrap();
+        assert_eq!(inodes.len(), 1);
+        assert_eq!(inodes[0].number, inode_a.number);
+        assert_eq!(inodes[0].btime, inode_a.btime);
+    }
+
+    // under a directory that is not a mount point, inodes are missing only
+    // if their recorded paths are all under it
+    let (c, d, e) = (dir.join("c"), dir.join("d"), dir.join("e"));
+    let mut gone = Vec::new();
+    for path in [&c, &d, &e] {
+        std::fs::write(path, "").unwrap();
+        gone.push(inode(path));
+    }
+    taginode::add(&connection, &gone, &["ikun"]).unwrap();
+    let tagged = taginode::get_inodes(&connection, &["ikun"]).unwrap().len();
+    taginode::index::record(&connection, &[
+        (gone[0].clone(), c.to_str().unwrap().to_string()),
+        (gone[1].clone(), d.to_str().unwrap().to_string()),
+        (gone[1].clone(), String::from("/elsewhere/d")),
+    ]).unwrap();
+    for path in [&c, &d, &e] {
+        std::fs::remove_file(path).unwrap();
+    }
+    let report = taginode::gc::gc(&connection, &[root], &Default::default(), true).unwrap();
+    assert_eq!(report.missing.iter().map(|inode| inode.number).collect::<Vec<_>>(), vec![gone[0].number]);
+    let report = taginode::gc::gc(&connection, &[root], &Default::default(), false).unwrap();
+    assert_eq!(report.relations, 1);
+    assert_eq!(taginode::get_inodes(&connection, &["ikun"]).unwrap().len(), tagged - 1);
+}
+
+#[test]
+fn t_opt_list() {
+    use std::collections::{BTreeMap};
+    use taginode::opt::{Opt, OptArg};
+    let opt_check = BTreeMap::from([
+        (b'd', Opt { arg: OptArg::Mandatory("."), value: "<directory>", help: "specify path to search file by tags, default \".\"", ..Opt::default() }),
+        (b'f', Opt { arg: OptArg::Mandatory("/.taginode.db"), value: "<db>", help: "specify db path to store data, default ~/.taginode.db", ..Opt::default() }),
+        (b'v', Opt { help: "verbose", ..Opt::default() }),
+    ]);
+    let args: Vec<String> = ["search", "-d", "/home", "-vd/data", "hello,world"]
+        .iter().map(|s| s.to_string()).collect();
+
+    let (options, operands) = taginode::opt::get_opt_list(&args, &opt_check).unwrap();
+    assert_eq!(options[&b'd'], vec!["/home", "/data"]);
+    assert_eq!(options[&b'f'], vec!["/.taginode.db"]);
+    assert_eq!(options[&b'v'], vec![""]);
+    assert_eq!(operands, vec!["search", "hello,world"]);
+
+    let (options, _) = taginode::opt::get_opt_per(&args, &opt_check).unwrap();
+    assert_eq!(options[&b'd'], "/data");
+}
+
+#[test]
+fn t_opt_command() {
+    use std::collections::{BTreeMap};
+    use taginode::opt::{Opt, OptArg};
+    let opt_check = BTreeMap::from([
+        (b'f', Opt { long: "db", arg: OptArg::Mandatory("/.taginode.db"), value: "<db>", help: "db path", ..Opt::default() }),
+        (b'd', Opt { long: "directory", arg: OptArg::Mandatory("."), value: "<directory>", commands: &["search"], help: "search path", ..Opt::default() }),
+        (b'c', Opt { long: "color", arg: OptArg::Optional("always"), value: "<when>", help: "colorize", ..Opt::default() }),
+        (b'h', Opt { long: "help", help: "help", ..Opt::default() }),
+    ]);
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+
+    let search = args(&["--db=/tmp/a.db", "search", "--directory", "/home", "-d/data", "--color", "hello"]);
+    let (options, operands) = taginode::opt::get_opt_command(&search, &opt_check).unwrap();
+    assert_eq!(options[&b'f'], vec!["/tmp/a.db"]);
+    assert_eq!(options[&b'd'], vec!["/home", "/data"]);
+    assert_eq!(options[&b'c'], vec!["always"]);
+    assert_eq!(operands, vec!["search", "hello"]);
+
+    let tag = args(&["tag", "--color=never", "--", "--help", "a"]);
+    let (options, operands) = taginode::opt::get_opt_command(&tag, &opt_check).unwrap();
+    assert_eq!(options[&b'c'], vec!["never"]);
+    assert!(!options.contains_key(&b'd'));
+    assert!(!options.contains_key(&b'h'));
+    assert_eq!(operands, vec!["tag", "--help", "a"]);
+
+    // the short form of an optional argument is a flag, so it clusters
+    let tag = args(&["tag", "-ch"]);
+    let (options, _) = taginode::opt::get_opt_command(&tag, &opt_check).unwrap();
+    assert_eq!(options[&b'c'], vec!["always"]);
+    assert_eq!(options[&b'h'], vec![""]);
+
+    for bad in [&["tag", "-d", "/home", "a"][..], &["tag", "--directory=/home"], &["--nope", "tag"],
+                &["--help=x", "tag"], &["search", "--db"]] {
+        assert!(taginode::opt::get_opt_command(&args(bad), &opt_check).is_err(), "{bad:?}");
+    }
+    // without a subcommand check every option is accepted
+    assert!(taginode::opt::get_opt_list(&args(&["tag", "-d/home"]), &opt_check).is_ok());
+
+    let usage = taginode::opt::usage(&opt_check, Some("tag"));
+    assert!(usage.contains("-c, --color[=<when>]"));
+    assert!(usage.contains("-f, --db <db>"));
+    assert!(!usage.contains("--directory"));
+    let usage = taginode::opt::usage(&opt_check, None);
+    assert!(usage.contains("[search]search path"));
+}
+
+#[test]
+fn t_completion() {
+    use std::collections::{BTreeMap};
+    use taginode::completion::Shell;
+    use taginode::opt::{Complete, Opt, OptArg};
+    let opt_check = BTreeMap::from([
+        (b'f', Opt { long: "db", arg: OptArg::Mandatory("/.taginode.db"), value: "<db>", complete: Complete::Files, help: "db path", ..Opt::default() }),
+        (b'c', Opt { long: "count", commands: &["list"], help: "count", ..Opt::default() }),
+    ]);
+    let commands = [("search", Complete::TagList), ("list", Complete::Words(&["tags"]))];
+
+    let bash = taginode::completion::generate(Shell::Bash, "taginode-cli", &commands, &opt_check);
+    assert!(bash.contains("complete -o nospace -F _taginode_cli taginode-cli"));
+    assert!(bash.contains("list) COMPREPLY=( $(compgen -W \"-c --count -f --db\" -- \"${cur}\") ) ;;"));
+    assert!(bash.contains("search) _taginode_cli_tags , ;;"));
+    assert!(bash.contains("-f|--db) ((i++)) ;;"));
+
+    let zsh = taginode::completion::generate(Shell::Zsh, "taginode-cli", &commands, &opt_check);
+    assert!(zsh.starts_with("#compdef taginode-cli\n"));
+    assert!(zsh.contains("-f|--db) _files; return ;;"));
+
+    let fish = taginode::completion::generate(Shell::Fish, "taginode-cli", &commands, &opt_check);
+    assert!(fish.contains("complete -c taginode-cli -s f -l db -r -F -d 'db path'"));
+    assert!(fish.contains("-a '(__taginode_cli_tags)'"));
+    // tags are read in plain output whatever the configured default
+    for script in [&bash, &zsh, &fish] {
+        assert!(script.contains("taginode-cli list tags -o plain 2>/dev/null"));
+    }
+
+    assert!("ksh".parse::<Shell>().is_err());
+}
+
+#[test]
+fn t_config() {
+    use taginode::config::Config;
+    let mut config = Config::default();
+    config.parse("# defaults\ndb = /tmp/a.db\nsearch-root = /home\nsearch-root = /data\ncross-device = yes\nignore = .git\n", "conf").unwrap();
+    assert_eq!(config.db.as_deref(), Some("/tmp/a.db"));
+    assert_eq!(config.search_roots, vec!["/home", "/data"]);
+    assert!(config.cross_device);
+    assert_eq!(config.ignore, vec![".git"]);
+    assert_eq!(config.output, "plain");
+
+    // the environment wins over the file, unset and empty variables do not count
+    let env = |name: &str| match name {
+        "TAGINODE_SEARCH_ROOTS" => Some(String::from("/srv:/mnt")),
+        "TAGINODE_OUTPUT" => Some(String::from("json")),
+        "TAGINODE_DB" => Some(String::new()),
+        _ => None,
+    };
+    config.apply_env(env).unwrap();
+    assert_eq!(config.db.as_deref(), Some("/tmp/a.db"));
+    assert_eq!(config.search_roots, vec!["/srv", "/mnt"]);
+    assert_eq!(config.output, "json");
+    assert!(config.cross_device);
+
+    for bad in ["db", "colour = red", "cross-device = maybe"] {
+        let err = Config::default().parse(bad, "conf").unwrap_err();
+        assert!(err.to_string().starts_with("conf:1: "), "{err}");
+    }
+    assert_eq!(config.ignore_files, vec![".taginodeignore"]);
+    assert!(config.apply_env(|name| (name == "TAGINODE_CROSS_DEVICE").then(|| String::from("2"))).is_err());
+}
+
+#[test]
+fn t_glob_match() {
+    use taginode::walk::glob_match;
+    for (pattern, name) in [("*", ".git"), ("node_mod*", "node_modules"), ("*.o", "a.o"), ("a*b*c", "aXbYbc"),
+                            ("?.txt", "a.txt"), ("[a-c]x", "bx"), ("[!0-9]", "z"), ("[]]", "]"), ("\\*", "*"), ("[", "[")] {
+        assert!(glob_match(pattern, name), "{pattern} {name}");
+    }
+    for (pattern, name) in [("*.o", "a.out"), ("?.txt", "ab.txt"), ("[a-c]x", "dx"), ("[!0-9]", "5"), ("\\*", "a"), ("a*b", "ac")] {
+        assert!(!glob_match(pattern, name), "{pattern} {name}");
+    }
+}
+
+#[test]
+fn t_walk() {
+    use taginode::walk::{walk, Matcher, WalkOptions};
+    let dir = temp_dir("walk");
+    for path in ["a/node_modules/m/y", "a/.git/g", "a/x", "b/deep/er/d", "b/deep/d", "b/keep.o", "b/drop.o"] {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+    std::fs::write(dir.join("a/.taginodeignore"), "# deps\nnode_modules/\n").unwrap();
+    std::fs::write(dir.join("b/.taginodeignore"), "*.o\n!keep.o\n/deep/er\n").unwrap();
+    let root = dir.to_str().unwrap();
+
+    let files = |options: &WalkOptions| {
+        let mut files = Vec::new();
+        let skipped = walk(root, options,
+            &mut |path, metadata| {
+                if metadata.is_file() && !path.ends_with(".taginodeignore") {
+                    files.push(path[root.len()+1..].to_string());
+                }
+                true
+            },
+            &mut |path, err| panic!("{path}: {err}"));
+        files.sort();
+        // a matcher leaves out what the walk does
+        let matcher = Matcher::new(root, options).unwrap();
+        for file in ALL {
+            assert_eq!(matcher.matches(file, file.split('/').count(), false), files.contains(&file.to_string()), "{file}");
+        }
+        (files, skipped)
+    };
+    const ALL: [&str; 7] = ["a/.git/g", "a/node_modules/m/y", "a/x", "b/deep/d", "b/deep/er/d", "b/drop.o", "b/keep.o"];
+    assert_eq!(files(&WalkOptions::default()), (ALL.map(String::from).to_vec(), 0));
+
+    let options = WalkOptions {
+        exclude: vec![String::from(".git/")],
+        ignore_files: vec![String::from(".taginodeignore")],
+        ..WalkOptions::default()
+    };
+    assert_eq!(files(&options), (vec!["a/x".to_string(), "b/deep/d".to_string(), "b/keep.o".to_string()], 4));
+    // rules of an ignore file are relative to its directory
+    let options = WalkOptions { exclude: vec![String::from("/deep")], ..WalkOptions::default() };
+    assert_eq!(files(&options).0.len(), ALL.len());
+    let options = WalkOptions { exclude: vec![String::from("b/**/d")], ..WalkOptions::default() };
+    assert_eq!(files(&options), (ALL.iter().filter(|f| !f.ends_with("/d")).map(|f| f.to_string()).collect(), 2));
+
+    let options = WalkOptions { max_depth: Some(2), ..WalkOptions::default() };
+    assert_eq!(files(&options), (vec!["a/x".to_string(), "b/drop.o".to_string(), "b/keep.o".to_string()], 3));
+}
+
+#[test]
+fn t_walk_parallel() {
+    use std::sync::Mutex;
+    use taginode::walk::{walk, walk_parallel, WalkOptions};
+    let dir = temp_dir("walk_parallel");
+    for i in 0..20 {
+        for path in [format!("d{i}/f"), format!("d{i}/s/t/g"), format!("d{i}/x.o")] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+    }
+    let root = dir.to_str().unwrap();
+    let missing = format!("{root}/missing");
+
+    for options in [
+        WalkOptions::default(),
+        WalkOptions { exclude: vec![String::from("*.o"), String::from("/d3")], max_depth: Some(3), ..WalkOptions::default() },
+    ] {
+        let mut expected = Vec::new();
+        let mut skipped = walk(root, &options, &mut |path, _| { expected.push(path.to_string()); true }, &mut |_, _| ());
+        skipped += walk(&missing, &options, &mut |_, _| true, &mut |_, _| ());
+        expected.sort();
+        for threads in [1, 4] {
+            let (paths, errors) = (Mutex::new(Vec::new()), Mutex::new(Vec::new()));
+            let n = walk_parallel(&[root, &missing], &options, threads,
+                &|path, _| { paths.lock().unwrap().push(path.to_string()); true },
+                &|path, _| errors.lock().unwrap().push(path.to_string()));
+            let mut paths = paths.into_inner().unwrap();
+            paths.sort();
+            assert_eq!(paths, expected);
+            assert_eq!(n, skipped);
+            assert_eq!(errors.into_inner().unwrap(), vec![missing.clone()]);
+        }
+    }
+
+    // directories visit turns down are not entered
+    let paths = Mutex::new(Vec::new());
+    walk_parallel(&[root], &WalkOptions::default(), 3,
+        &|path, _| { paths.lock().unwrap().push(path.to_string()); !path.ends_with("/s") },
+        &|_, _| ());
+    assert_eq!(paths.into_inner().unwrap().len(), 1 + 20 * 4);
+}
+
+#[test]
+fn t_walk_parallel_repeated() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use taginode::walk::{walk_parallel, WalkOptions};
+    let dir = temp_dir("walk_parallel_repeated");
+    for i in 0..20 {
+        for j in 0..5 {
+            let path = dir.join(format!("d{i}/f{j}"));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+    }
+    let root = dir.to_str().unwrap().to_string();
+
+    // Threads stealing from each other once deadlocked after some runs.
+    let (done, finished) = mpsc::channel();
+    std::thread::spawn(move || {
+        for _ in 0..2000 {
+            let count = AtomicUsize::new(0);
+            walk_parallel(&[&root], &WalkOptions::default(), 4,
+                &|_, _| { count.fetch_add(1, Ordering::Relaxed); true }, &|_, _| ());
+            assert_eq!(count.into_inner(), 1 + 20 * 6);
+        }
+        done.send(()).unwrap();
+    });
+    finished.recv_timeout(Duration::from_secs(120)).expect("walk_parallel hung or failed");
+}
+
+#[test]
+fn t_find() {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use taginode::find::{Event, Find, Target};
+    use taginode::walk::WalkOptions;
+    let dir = temp_dir("find");
+    for path in ["a/f", "a/s/g", "b/h"] {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, path.to_str().unwrap()).unwrap();
+    }
+    std::fs::hard_link(dir.join("a/f"), dir.join("b/link")).unwrap();
+    let root = dir.to_str().unwrap();
+    let inode = |path: &str| INode::from_metadata(&std::fs::symlink_metadata(dir.join(path)).unwrap());
+    let mut inodes: HashMap<u64, HashMap<u64, INode>> = HashMap::new();
+    for path in ["a/f", "a/s/g"] {
+        let inode = inode(path);
+        inodes.entry(inode.device).or_default().insert(inode.number, inode);
+    }
+    let hashes = [taginode::content::hash(&format!("{root}/b/h")).unwrap().sha256].into_iter().collect();
+
+    for threads in [1, 4] {
+        let run = |target, duplicates| {
+            let (found, duplicate) = (Mutex::new(Vec::new()), Mutex::new(Vec::new()));
+            let find = Find { target, options: Default::default(), duplicates, threads };
+            find.run(&[root], &|event| {
+                match event {
+                    Event::Found(path, _, file) => found.lock().unwrap().push((path[root.len()..].to_string(), file.is_some())),
+                    Event::Duplicate(path, _) => duplicate.lock().unwrap().push(path.to_string()),
+                    Event::Error(path, err) => panic!("{path}: {err}"),
+                }
+                true
+            });
+            let mut found = found.into_inner().unwrap();
+            found.sort();
+            (found, duplicate.into_inner().unwrap().len())
+        };
+        let (found, duplicates) = run(Target::Inodes(&inodes), false);
+        assert_eq!(found.len() + duplicates, 3);
+        assert!(found.contains(&(String::from("/a/s/g"), false)));
+        assert_eq!(run(Target::Inodes(&inodes), true), (vec![
+            (String::from("/a/f"), false), (String::from("/a/s/g"), false), (String::from("/b/link"), false),
+        ], 0));
+        assert_eq!(run(Target::Contents(&hashes), false), (vec![(String::from("/b/h"), true)], 1));
+
+        // returning false stops the walk
+        let found = Mutex::new(0);
+        Find { target: Target::Inodes(&inodes), options: Default::default(), duplicates: true, threads }
+            .run(&[root], &|_| { *found.lock().unwrap() += 1; false });
+        let found = found.into_inner().unwrap();
+        assert!(found >= 1 && (found == 1 || threads > 1));
+    }
+
+    // located paths are checked like walked ones, and only those under a root count
+    let located = [format!("{root}/a/f"), format!("{root}/b/link"), format!("{root}/a/s/g"), String::from("/elsewhere/f")];
+    let located: Vec<&str> = located.iter().map(String::as_str).collect();
+    for duplicates in [false, true] {
+        let events = Mutex::new(Vec::new());
+        let find = Find { target: Target::Inodes(&inodes), options: Default::default(), duplicates, threads: 1 };
+        find.located(&[&format!("{root}/a"), &format!("{root}/b")], &located, &|event| {
+            events.lock().unwrap().push(match event {
+                Event::Found(path, _, _) => format!("found {}", &path[root.len()..]),
+                Event::Duplicate(path, first) => format!("{} same as {}", &path[root.len()..], &first[root.len()..]),
+                Event::Error(path, err) => panic!("{path}: {err}"),
+            });
+            true
+        }).unwrap();
+        let expect = if duplicates {
+            vec!["found /a/f", "found /b/link", "found /a/s/g"]
+        } else {
+            vec!["found /a/f", "/b/link same as /a/f", "found /a/s/g"]
+        };
+        assert_eq!(events.into_inner().unwrap(), expect);
+    }
+
+    // and spelled under their root as a run spells them
+    let spelled = format!("{root}/./a/");
+    let find = Find { target: Target::Inodes(&inodes), options: Default::default(), duplicates: false, threads: 1 };
+    let (walked, found) = (Mutex::new(Vec::new()), Mutex::new(Vec::new()));
+    let push = |paths: &Mutex<Vec<String>>, event: Event| {
+        if let Event::Found(path, _, _) = event {
+            paths.lock().unwrap().push(path.to_string());
+        }
+        true
+    };
+    find.run(&[&spelled], &|event| push(&walked, event));
+    find.located(&[&spelled], &located, &|event| push(&found, event)).unwrap();
+    let (mut walked, mut found) = (walked.into_inner().unwrap(), found.into_inner().unwrap());
+    walked.sort();
+    found.sort();
+    assert_eq!(found, vec![format!("{spelled}f"), format!("{spelled}s/g")]);
+    assert_eq!(found, walked);
+
+    // and left out by the walk rules like walked ones
+    let events = Mutex::new(Vec::new());
+    let options = WalkOptions { exclude: vec![String::from("s")], ..WalkOptions::default() };
+    Find { target: Target::Inodes(&inodes), options, duplicates: false, threads: 1 }
+        .located(&[root], &located, &|event| {
+            if let Event::Found(path, _, _) = event {
+                events.lock().unwrap().push(path[root.len()..].to_string());
+            }
+            true
+        }).unwrap();
+    assert_eq!(events.into_inner().unwrap(), vec!["/a/f"]);
+}
+
+#[test]
+fn t_output() {
+    use taginode::output::{Field, Format, Writer};
+    let records: Vec<Vec<(&str, Field)>> = vec![
+        vec![("path", "a \"b\",c".into()), ("inode", 12951634006u64.into()), ("btime", None.into()),
+             ("tags", vec!["ikun".to_string(), "man".to_string()].into())],
+        vec![("path", "d\ne".into()), ("inode", 1u64.into()), ("btime", Some(1665935055).into()),
+             ("tags", Vec::<String>::new().into())],
+    ];
+    let render = |format: Format| {
+        let mut buf = Vec::new();
+        let mut out = Writer::new(&mut buf, format, &["path", "tags"]);
+        for record in &records {
+            out.write(record).unwrap();
+        }
+        out.finish().unwrap();
+        String::from_utf8(buf).unwrap()
+    };
+    assert_eq!(render(Format::Plain), "a \"b\",c\tikun,man\nd\ne\t\n");
+    assert_eq!(render(Format::Nul), "a \"b\",c\tikun,man\0d\ne\t\0");
+    assert_eq!(render(Format::JsonLines), concat!(
+        r#"{"path":"a \"b\",c","inode":12951634006,"btime":null,"tags":["ikun","man"]}"#, "\n",
+        r#"{"path":"d\ne","inode":1,"btime":1665935055,"tags":[]}"#, "\n",
+    ));
+    assert_eq!(render(Format::Json), concat!(
+        "[\n",
+        r#"  {"path":"a \"b\",c","inode":12951634006,"btime":null,"tags":["ikun","man"]},"#, "\n",
+        r#"  {"path":"d\ne","inode":1,"btime":1665935055,"tags":[]}"#, "\n",
+        "]\n",
+    ));
+    assert_eq!(render(Format::Csv), "path,inode,btime,tags\n\"a \"\"b\"\",c\",12951634006,,\"ikun,man\"\n\"d\ne\",1,1665935055,\n");
+    assert!("xml".parse::<Format>().is_err());
+}
+
+// The schema written by sql::init before databases were versioned.
+const SCHEMA_V0: &str = "
+    CREATE TABLE tags (
+        `id` INTEGER PRIMARY KEY, `name` TEXT NOT NULL, `inode_num` INTEGER NOT NULL DEFAULT 0,
+        `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP, UNIQUE(name), CHECK(name <> ''));
+    CREATE TABLE inodes (
+        `id` INTEGER PRIMARY KEY, `device` INTEGER NOT NULL, `number` INTEGER NOT NULL,
+        `btime` TIMESTAMP DEFAULT NULL, `tag_num` INTEGER NOT NULL DEFAULT 0,
+        `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE(device, number), CHECK(device <> 0 AND number <> 0));
+    CREATE TABLE relation_tag_inode (
+        `id` INTEGER PRIMARY KEY, `tag_id` INTEGER NOT NULL, `inode_id` INTEGER NOT NULL,
+        `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE(tag_id, inode_id), CHECK(tag_id <> 0 AND inode_id <> 0));
+    CREATE TABLE file (
+        `id` INTEGER PRIMARY KEY, `md5` TEXT NOT NULL UNIQUE, `sha256` TEXT NOT NULL UNIQUE,
+        `path` TEXT, `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP);
+    CREATE TABLE relation_tag_file (
+        `id` INTEGER PRIMARY KEY, `tag_id` INTEGER NOT NULL, `file_id` INTEGER NOT NULL,
+        `create_at` TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE(tag_id, file_id), CHECK(tag_id <> 0 AND file_id <> 0));
+    INSERT INTO tags(name) VALUES('ikun'), ('basketball');
+    INSERT INTO inodes(device, number, btime) VALUES(16777220, 12951634006, NULL),
+        (16777220, 12951634036, strftime('%Y-%m-%d %H:%M:%S', 1665935055, 'unixepoch'));
+    INSERT INTO relation_tag_inode(tag_id, inode_id) VALUES(1, 1), (1, 2), (2, 2);
+";
+
+// The same store tests run against every storage.
+fn stores() -> Vec<taginode::TagStore> {
+    vec![
+        taginode::TagStore::open_in_memory().unwrap(),
+        taginode::TagStore::with_storage(taginode::memory::MemoryStorage::new()),
+    ]
+}
+
+// A fresh directory for a test, deleted when dropped, also when the test fails.
+struct TempDir(std::path::PathBuf);
+
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn temp_dir(name: &str) -> TempDir {
+    let dir = std::env::temp_dir().join(format!("taginode_test_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+}
+
+fn temp_db(name: &str) -> String {
+    let db = std::env::temp_dir().join(format!("taginode_test_{}_{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    db.to_str().unwrap().to_string()
+}
+
+#[test]
+fn t_migrate() {
+    let db = temp_db("migrate");
+    sqlite3::open(&db).unwrap().execute(SCHEMA_V0).unwrap();
+
+    let connection = taginode::sql::init(&db).unwrap();
+    assert_eq!(taginode::sql::version(&connection).unwrap(), taginode::sql::SCHEMA_VERSION);
+    let expect: Vec<String> = ["basketball", "ikun"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(taginode::list_tags(&connection).unwrap(), expect);
+    let inodes = taginode::get_inodes(&connection, &["ikun", "basketball"]).unwrap();
+    assert_eq!(inodes.len(), 1);
+    assert_eq!(inodes[0].btime, Some(1665935055));
+    let inode = INode{ device: 16777220, number: 12951634006, btime: None };
+    taginode::index::record(&connection, &[(inode.clone(), String::from("/ikun"))]).unwrap();
+    assert_eq!(taginode::index::paths(&connection, &inode).unwrap(), vec![String::from("/ikun")]);
+    drop(connection);
+
+    // reopening an up to date database is a no-op
+    let connection = taginode::sql::init(&db).unwrap();
+    assert_eq!(taginode::sql::version(&connection).unwrap(), taginode::sql::SCHEMA_VERSION);
+
+    connection.execute(format!("PRAGMA user_version = {}", taginode::sql::SCHEMA_VERSION + 1)).unwrap();
+    drop(connection);
+    match taginode::sql::init(&db) {
+        Err(taginode::Error::SchemaMismatch(_)) => (),
+        ret => panic!("expect SchemaMismatch, got {:?}", ret.err()),
+    }
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn t_tag_counts() {
+    use taginode::Tag;
+    let connection = taginode::sql::init(":memory:").unwrap();
+    let inode = |number| INode{ device: 16777220, number, btime: None };
+    taginode::add(&connection, &[inode(1), inode(2), inode(3)], &["ikun"]).unwrap();
+    taginode::add(&connection, &[inode(1), inode(2)], &["ikun", "basketball"]).unwrap();
+    taginode::add(&connection, &[inode(3)], &["chicken", "man"]).unwrap();
+    taginode::remove(&connection, &[inode(3)], &["man"], false).unwrap();
+
+    let tag = |name: &str, inode_num| Tag{ name: name.to_string(), inode_num };
+    assert_eq!(taginode::list_tag_counts(&connection).unwrap(),
+        vec![tag("ikun", 3), tag("basketball", 2), tag("chicken", 1), tag("man", 0)]);
+
+    taginode::merge_tags(&connection, &["chicken"], "basketball").unwrap();
+    assert_eq!(taginode::list_tag_counts(&connection).unwrap(),
+        vec![tag("basketball", 3), tag("ikun", 3), tag("man", 0)]);
+
+    let mut cursor = connection.prepare("SELECT number, tag_num FROM inodes ORDER BY number").unwrap().cursor();
+    let mut tag_nums = Vec::new();
+    while let Some(row) = cursor.next().unwrap() {
+        tag_nums.push((row[0].as_integer().unwrap(), row[1].as_integer().unwrap()));
+    }
+    assert_eq!(tag_nums, vec![(1, 2), (2, 2), (3, 2)]);
+}
+
+#[test]
+fn t_migrate_tag_counts() {
+    let db = temp_db("migrate_tag_counts");
+    sqlite3::open(&db).unwrap().execute(SCHEMA_V0).unwrap();
+    let connection = taginode::sql::init(&db).unwrap();
+    let counts: Vec<(String, u64)> = taginode::list_tag_counts(&connection).unwrap()
+        .into_iter().map(|tag| (tag.name, tag.inode_num)).collect();
+    assert_eq!(counts, vec![(String::from("ikun"), 2), (String::from("basketball"), 1)]);
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn t_foreign_keys() {
+    let db = temp_db("foreign_keys");
+    {
+        let connection = sqlite3::open(&db).unwrap();
+        connection.execute(SCHEMA_V0).unwrap();
+        // relations to a deleted tag (9) and a deleted inode (9)
+        connection.execute("INSERT INTO relation_tag_inode(tag_id, inode_id) VALUES(9, 1), (1, 9)").unwrap();
+    }
+    let connection = taginode::sql::init(&db).unwrap();
+    let count = |table: &str| {
+        let mut cursor = connection.prepare(format!("SELECT COUNT(*) FROM {table}")).unwrap().cursor();
+        cursor.next().unwrap().unwrap()[0].as_integer().unwrap()
+    };
+    assert_eq!(count("relation_tag_inode"), 3);
+    let inode = INode{ device: 16777220, number: 12951634006, btime: None };
+    assert_eq!(taginode::get_tags(&connection, inode.clone()).unwrap(), InodeTags::Tagged(vec![String::from("ikun")]));
+
+    // deleting a tag or an inode takes its relations and paths along
+    taginode::index::record(&connection, &[(inode.clone(), String::from("/ikun"))]).unwrap();
+    connection.execute("DELETE FROM tags WHERE name = 'basketball'").unwrap();
+    assert_eq!(count("relation_tag_inode"), 2);
+    connection.execute("DELETE FROM inodes WHERE number = 12951634006").unwrap();
+    assert_eq!(count("relation_tag_inode"), 1);
+    assert_eq!(count("paths"), 0);
+    assert_eq!(taginode::list_tag_counts(&connection).unwrap()[0].inode_num, 1);
+
+    // and relations to rows that do not exist are refused
+    assert!(connection.execute("INSERT INTO relation_tag_inode(tag_id, inode_id) VALUES(1, 9)").is_err());
+    std::fs::remove_file(&db).unwrap();
+}
+
+#[test]
+fn t_transaction() {
+    let connection = taginode::sql::init(":memory:").unwrap();
+    let inode = |number| INode{ device: 16777220, number, btime: None };
+
+    // a failure in the last step of add leaves no tags or inodes behind
+    connection.execute("
+        CREATE TEMP TRIGGER fail BEFORE INSERT ON relation_tag_inode
+        BEGIN SELECT RAISE(ABORT, 'fail'); END;
+        ").unwrap();
+    assert!(taginode::add(&connection, &[inode(1)], &["ikun"]).is_err());
+    assert!(taginode::list_tags(&connection).unwrap().is_empty());
+    let mut cursor = connection.prepare("SELECT COUNT(*) FROM inodes").unwrap().cursor();
+    assert_eq!(cursor.next().unwrap().unwrap()[0].as_integer(), Some(0));
+    connection.execute("DROP TRIGGER fail").unwrap();
+
+    // grouped calls commit or roll back together
+    let ret: taginode::Result<()> = taginode::transaction(&connection, || {
+        taginode::add(&connection, &[inode(1)], &["ikun"])?;
+        taginode::add(&connection, &[inode(2)], &["basketball"])?;
+        Err(taginode::Error::InvalidTag(String::new()))
+    });
+    assert!(ret.is_err());
+    assert!(taginode::list_tags(&connection).unwrap().is_empty());
+
+    let n = taginode::transaction(&connection, || {
+        for number in 1..=100 {
+            taginode::add(&connection, &[inode(number)], &["ikun"])?;
+        }
+        taginode::remove(&connection, &[inode(1)], &["ikun"], false)?;
+        Ok(99)
+    }).unwrap();
+    assert_eq!(taginode::get_inodes(&connection, &["ikun"]).unwrap().len(), n);
+}
+
+#[test]
+fn t_concurrent() {
+    let db = temp_db("concurrent");
+    drop(taginode::sql::init(&db).unwrap());
+    let inode = |number| INode{ device: 16777220, number, btime: None };
+
+    let mut threads = Vec::new();
+    for writer in 0..4u64 {
+        let db = db.clone();
+        threads.push(std::thread::spawn(move || {
+            let connection = taginode::sql::init(&db).unwrap();
+            for i in 0..25 {
+                let number = writer * 1000 + i + 1;
+                taginode::add(&connection, &[inode(number)], &["ikun", &format!("writer{writer}")]).unwrap();
+                if i % 5 == 0 {
+                    taginode::remove(&connection, &[inode(number)], &["ikun"], false).unwrap();
+                }
+            }
+        }));
+    }
+    for _ in 0..4 {
+        let db = db.clone();
+        threads.push(std::thread::spawn(move || {
+            let connection = taginode::sql::init(&db).unwrap();
+            let query = taginode::query::parse("ikun or writer0").unwrap();
+            for _ in 0..25 {
+                taginode::search(&connection, &query).unwrap();
+                taginode::list_tag_counts(&connection).unwrap();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let connection = taginode::sql::init(&db).unwrap();
+    let mut cursor = connection.prepare("PRAGMA journal_mode").unwrap().cursor();
+    assert_eq!(cursor.next().unwrap().unwrap()[0].as_string(), Some("wal"));
+    assert_eq!(taginode::get_inodes(&connection, &["ikun"]).unwrap().len(), 80);
+    for writer in 0..4 {
+        assert_eq!(taginode::get_inodes(&connection, &[&format!("writer{writer}")]).unwrap().len(), 25);
+    }
+    drop(cursor);
+    drop(connection);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{db}{suffix}"));
+    }
+}
+
+#[test]
+fn t_iter() {
+    for store in stores() {
+        let inodes: Vec<INode> = (1..=2000).map(|number| INode{ device: 16777220, number, btime: None }).collect();
+        store.add(&inodes, &["ikun", "basketball"]).unwrap();
+        store.add(&inodes[..10], &["man"]).unwrap();
+
+        let mut iter = store.get_inodes_iter(&["ikun", "basketball"]).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().number, 1);
+        assert_eq!(iter.count(), 1999);
+
+        let query = taginode::query::parse("man and not basketball").unwrap();
+        assert_eq!(store.search_iter(&query).unwrap().count(), 0);
+        let query = taginode::query::parse("man").unwrap();
+        let numbers: Vec<u64> = store.search_iter(&query).unwrap()
+            .map(|inode| inode.unwrap().number).collect();
+        assert_eq!(numbers, (1..=10).collect::<Vec<u64>>());
+
+        let tag_names: Vec<String> = store.list_tags_iter().unwrap().map(Result::unwrap).collect();
+        assert_eq!(tag_names, store.list_tags().unwrap());
+        let tag = store.list_tag_counts_iter().unwrap().last().unwrap().unwrap();
+        assert_eq!((tag.name.as_str(), tag.inode_num), ("man", 10));
+        let tag_names: Vec<String> = store.get_tags_iter(&inodes[0]).unwrap().tags().unwrap().map(Result::unwrap).collect();
+        assert_eq!(tag_names, vec!["ikun", "basketball", "man"]);
+    }
+}
+
+#[test]
+fn t_store() {
+    use taginode::{Stats, TagStore};
+    let db = temp_db("store");
+    {
+        let store = TagStore::open(&db).unwrap();
+        let inode = |number| INode{ device: 16777220, number, btime: None };
+        store.transaction(|store| {
+            store.add(&[inode(1), inode(2)], &["ikun", "basketball"])?;
+            store.add(&[inode(3)], &["man"])?;
+            store.record_paths(&[(inode(1), String::from("/ikun"))])
+        }).unwrap();
+        store.remove(&[inode(2)], &["basketball"], false).unwrap();
+        store.rename_tag("man", "chicken").unwrap();
+
+        let query = taginode::query::parse("ikun and not basketball").unwrap();
+        assert_eq!(store.search(&query).unwrap().iter().map(|inode| inode.number).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(store.get_tags(&inode(1)).unwrap().tags().unwrap(), vec!["ikun", "basketball"]);
+        assert_eq!(store.list_tags().unwrap(), vec!["basketball", "chicken", "ikun"]);
+        assert_eq!(store.stats().unwrap(), Stats { tags: 3, inodes: 3, relations: 4, files: 0, file_relations: 0, paths: 1 });
+    }
+
+    let store = TagStore::open_readonly(&db).unwrap();
+    assert_eq!(store.get_inodes(&["ikun"]).unwrap().len(), 2);
+    match store.add(&[INode{ device: 16777220, number: 4, btime: None }], &["ikun"]) {
+        // SQLITE_READONLY
+        Err(err @ taginode::Error::Database { code: Some(8), .. }) => assert!(!err.is_busy()),
+        ret => panic!("expect a read-only database error, got {ret:?}"),
+    }
+    assert!(store.rename_tag("ikun", "kun").is_err());
+    assert_eq!(store.stats().unwrap().relations, 4);
+    drop(store);
+    std::fs::remove_file(&db).unwrap();
+    match TagStore::open_readonly(&db) {
+        Err(taginode::Error::NotFound(_)) => (),
+        ret => panic!("expect NotFound, got {:?}", ret.err()),
+    }
+    assert!(matches!(taginode::sql::init_readonly(&db), Err(taginode::Error::NotFound(_))));
+    assert!(!std::path::Path::new(&db).exists());
+
+    let store = TagStore::open_in_memory().unwrap();
+    assert_eq!(store.stats().unwrap(), Stats::default());
+}
+
+#[test]
+fn t_store_transaction() {
+    let inode = |number| INode{ device: 16777220, number, btime: None };
+    for store in stores() {
+        let ret: taginode::Result<()> = store.transaction(|store| {
+            store.add(&[inode(1)], &["ikun"])?;
+            store.transaction(|store| store.add(&[inode(2)], &["basketball"]))?;
+            Err(taginode::Error::InvalidTag(String::new()))
+        });
+        assert!(ret.is_err());
+        assert!(store.list_tags().unwrap().is_empty());
+
+        store.transaction(|store| {
+            store.add(&[inode(1)], &["ikun"])?;
+            let ret = store.transaction(|store| {
+                store.add(&[inode(2)], &["basketball"])?;
+                store.merge_tags(&["nothing"], "ikun")
+            });
+            assert!(matches!(ret, Err(taginode::Error::NotFound(_))));
+            Ok(())
+        }).unwrap();
+        assert_eq!(store.list_tags().unwrap(), vec!["ikun"]);
+        assert_eq!(store.stats().unwrap().relations, 1);
+    }
+
+    let store = taginode::TagStore::with_storage(taginode::memory::MemoryStorage::new());
+    assert!(matches!(store.paths(&inode(1)), Err(taginode::Error::Unsupported(_))));
+}
+
+#[test]
+fn t_get_tags_state() {
+    for store in stores() {
+        let inode = |number, btime| INode{ device: 16777220, number, btime };
+        store.add(&[inode(1, Some(1665935055)), inode(2, None)], &["ikun"]).unwrap();
+        store.remove(&[inode(2, None)], &["ikun"], false).unwrap();
+
+        assert_eq!(store.get_tags(&inode(1, Some(1665935055))).unwrap(), InodeTags::Tagged(vec![String::from("ikun")]));
+        // a btime unknown on either side matches
+        assert_eq!(store.get_tags(&inode(1, None)).unwrap(), InodeTags::Tagged(vec![String::from("ikun")]));
+        assert_eq!(store.get_tags(&inode(2, Some(1665935099))).unwrap(), InodeTags::Tagged(Vec::new()));
+        // the inode number now belongs to a file created later
+        assert_eq!(store.get_tags(&inode(1, Some(1665935099))).unwrap(), InodeTags::Stale { btime: Some(1665935055) });
+        assert_eq!(store.get_tags(&inode(3, None)).unwrap(), InodeTags::Unknown);
+        assert!(store.get_tags_iter(&inode(3, None)).unwrap().tags().is_none());
+    }
+}
+
+#[test]
+fn t_inode_reuse() {
+    for store in stores() {
+        let inode = |btime| INode{ device: 16777220, number: 1, btime };
+        store.add(&[inode(Some(100))], &["old-private"]).unwrap();
+        let indexed = store.record_paths(&[(inode(Some(100)), String::from("/old"))]).is_ok();
+        // an unknown btime is taken to be the same file
+        store.add(&[inode(None)], &["old"]).unwrap();
+        assert_eq!(store.get_tags(&inode(Some(100))).unwrap(), InodeTags::Tagged(vec![String::from("old-private"), String::from("old")]));
+
+        store.add(&[inode(Some(200))], &["holiday"]).unwrap();
+        assert_eq!(store.get_tags(&inode(Some(200))).unwrap(), InodeTags::Tagged(vec![String::from("holiday")]));
+        assert!(store.search(&taginode::query::parse("old-private").unwrap()).unwrap().is_empty());
+        assert_eq!(store.search(&taginode::query::parse("holiday").unwrap()).unwrap().len(), 1);
+        if indexed {
+            assert!(store.paths(&inode(Some(200))).unwrap().is_empty());
+        }
+    }
+}
+
+#[test]
+fn t_inode_range() {
+    let numbers = [1, i64::MAX as u64, i64::MAX as u64 + 1, u64::MAX - 1, u64::MAX];
+    let inodes: Vec<INode> = numbers.iter().flat_map(|device| {
+        numbers.iter().map(|number| INode{ device: *device, number: *number, btime: Some(1665935055) })
+    }).collect();
+    for store in stores() {
+        store.add(&inodes, &["ikun"]).unwrap();
+        let found = store.get_inodes(&["ikun"]).unwrap();
+        assert_eq!(found.len(), inodes.len());
+        for (inode, expect) in found.iter().zip(&inodes) {
+            assert_eq!((inode.device, inode.number, inode.btime), (expect.device, expect.number, expect.btime));
+            assert_eq!(store.get_tags(expect).unwrap(), InodeTags::Tagged(vec![String::from("ikun")]));
+        }
+        let query = taginode::query::parse("ikun").unwrap();
+        assert_eq!(store.search(&query).unwrap().len(), inodes.len());
+
+        store.remove(&inodes[inodes.len() - 1..], &["ikun"], false).unwrap();
+        assert_eq!(store.get_inodes(&["ikun"]).unwrap().len(), inodes.len() - 1);
+        assert_eq!(store.list_tag_counts().unwrap()[0].inode_num, inodes.len() as u64 - 1);
+    }
+
+    let connection = taginode::sql::init(":memory:").unwrap();
+    let inode = &inodes[inodes.len() - 1];
+    taginode::add(&connection, std::slice::from_ref(inode), &["ikun"]).unwrap();
+    taginode::index::record(&connection, &[(inode.clone(), String::from("/ikun"))]).unwrap();
+    assert_eq!(taginode::index::paths(&connection, inode).unwrap(), vec![String::from("/ikun")]);
+}
+
+#[test]
+fn t_migrate_inode_range() {
+    let db = temp_db("migrate_inode_range");
+    {
+        let connection = sqlite3::open(&db).unwrap();
+        connection.execute(SCHEMA_V0).unwrap();
+        // what older versions wrote for inode numbers above i64::MAX
+        connection.execute("
+            INSERT INTO inodes(device, number) VALUES
+                (16777220, 9223372036854777856), (16777220, 18446744073709551615);
+            INSERT INTO relation_tag_inode(tag_id, inode_id) VALUES(2, 3), (2, 4);
+            ").unwrap();
+    }
+    let connection = taginode::sql::init(&db).unwrap();
+    let mut numbers: Vec<u64> = taginode::get_inodes(&connection, &["basketball"]).unwrap()
+        .iter().map(|inode| inode.number).collect();
+    numbers.sort();
+    // 18446744073709551615 was rounded up to 2^64, which does not fit
+    assert_eq!(numbers, vec![12951634036, 9223372036854777856]);
+    assert_eq!(taginode::list_tag_counts(&connection).unwrap()[0].inode_num, 2);
+    drop(connection);
+    std::fs::remove_file(&db).unwrap();
+}